@@ -1,8 +1,119 @@
-use std::io::Cursor;
-
-use epd_home::screen::Screen;
+use chrono::{Duration, SecondsFormat, Utc};
+use epd_home::{
+    refresh,
+    screen::{self, Screen},
+};
+use serde::Deserialize;
 use worker::*;
 
+/// How long devices wait before asking again for a frame whose hint has passed, the same as the
+/// web server's default render interval.
+const BACK_OFF: Duration = Duration::minutes(1);
+
+/// Screen parameters, using the same names as the web server's query string.
+///
+/// Each field is taken from the request's query string if present, then from the named screen
+/// stored as JSON in the `SCREENS` KV namespace (for `/screens/{name}.bmp`), then from the
+/// `LAT`, `LON`, `TIMEZONE`, `STOP_CODE` and `DITHER` environment variables.
+#[derive(Deserialize, Default)]
+struct ScreenOptions {
+    lat: Option<f64>,
+    lon: Option<f64>,
+    timezone: Option<String>,
+    stop_code: Option<String>,
+    dither: Option<String>,
+}
+
+impl ScreenOptions {
+    fn from_url(url: &Url) -> Result<Self> {
+        let mut options = Self::default();
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "lat" => options.lat = Some(parse_number(&key, &value)?),
+                "lon" => options.lon = Some(parse_number(&key, &value)?),
+                "timezone" => options.timezone = Some(value.into_owned()),
+                "stop_code" => options.stop_code = Some(value.into_owned()),
+                "dither" => options.dither = Some(value.into_owned()),
+                _ => (),
+            }
+        }
+        Ok(options)
+    }
+
+    fn from_env(env: &Env) -> Result<Self> {
+        let var = |name: &str| env.var(name).ok().map(|value| value.to_string());
+        let options = Self {
+            lat: var("LAT").map(|value| parse_number("LAT", &value)).transpose()?,
+            lon: var("LON").map(|value| parse_number("LON", &value)).transpose()?,
+            timezone: var("TIMEZONE"),
+            stop_code: var("STOP_CODE"),
+            dither: var("DITHER"),
+        };
+        Ok(options)
+    }
+
+    /// Fills in anything not set here from `other`.
+    fn or(self, other: Self) -> Self {
+        Self {
+            lat: self.lat.or(other.lat),
+            lon: self.lon.or(other.lon),
+            timezone: self.timezone.or(other.timezone),
+            stop_code: self.stop_code.or(other.stop_code),
+            dither: self.dither.or(other.dither),
+        }
+    }
+}
+
+fn parse_number(name: &str, value: &str) -> Result<f64> {
+    value
+        .parse()
+        .map_err(|_| Error::RustError(format!("Invalid {}: {}", name, value)))
+}
+
+async fn render(options: ScreenOptions) -> Result<Response> {
+    let (Some(lat), Some(lon), Some(timezone)) = (options.lat, options.lon, &options.timezone) else {
+        return Response::error("lat, lon and timezone are required", 400);
+    };
+    let stop_codes: Vec<&str> = options
+        .stop_code
+        .as_deref()
+        .map(|codes| codes.split(',').collect())
+        .unwrap_or_default();
+
+    let screen = Screen::new(lat, lon, timezone, &stop_codes).and_then(|screen| match &options.dither {
+        Some(dither) => screen.with_dither(dither),
+        None => Ok(screen),
+    });
+    let rendered = match screen {
+        Ok(screen) => screen.render_with_refresh().await,
+        Err(err) => Err(err),
+    };
+    let rendered = match rendered {
+        Ok(rendered) => rendered,
+        Err(err @ (screen::Error::InvalidTimezone | screen::Error::UnknownDither(_))) => {
+            return Response::error(err.to_string(), 400);
+        }
+        Err(err) => {
+            log::error!("Failed to render: {}", err);
+            return Response::error(err.to_string(), 502);
+        }
+    };
+
+    let bmp = rendered.frame.to_bmp().map_err(|err| Error::RustError(err.to_string()))?;
+
+    // When the screen next changes, so panels can deep-sleep until then
+    let (next_refresh, refresh_after) = refresh::hint(rendered.next_refresh, Utc::now(), BACK_OFF);
+
+    let mut headers = Headers::default();
+    headers.append("Content-Type", "image/bmp")?;
+    headers.append("Refresh-After", &refresh_after.to_string())?;
+    headers.append("X-Next-Refresh", &next_refresh.to_rfc3339_opts(SecondsFormat::Secs, true))?;
+
+    let resp = Response::from_bytes(bmp)?.with_headers(headers);
+
+    Ok(resp)
+}
+
 #[event(start)]
 fn start() {
     console_log::init_with_level(log::Level::Debug).unwrap();
@@ -10,20 +121,27 @@ fn start() {
 }
 
 #[event(fetch)]
-async fn main(req: Request, env: Env, ctx: Context) -> Result<Response> {
+async fn main(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    let url = req.url()?;
+    let path = url.path();
 
-    let mut buffer = Cursor::new(Vec::<u8>::new());
-    
-    Screen::new(-36.75, 174.625, "Pacific/Auckland", "3889").unwrap()
-        .render(&mut buffer)
-        .await
-        .unwrap();
+    let named = if path == "/home.bmp" {
+        ScreenOptions::default()
+    } else if let Some(name) = path
+        .strip_prefix("/screens/")
+        .and_then(|name| name.strip_suffix(".bmp"))
+    {
+        match env.kv("SCREENS")?.get(name).json::<ScreenOptions>().await? {
+            Some(options) => options,
+            None => return Response::error("Unknown screen", 404),
+        }
+    } else {
+        return Response::error("Not found", 404);
+    };
 
-    let mut headers = Headers::default();
-    headers.append("Content-Type", "image/bmp").unwrap();
+    let options = ScreenOptions::from_url(&url)?
+        .or(named)
+        .or(ScreenOptions::from_env(&env)?);
 
-    let resp = Response::from_bytes(buffer.into_inner()).unwrap()
-        .with_headers(headers);
-
-    Ok(resp)
+    render(options).await
 }
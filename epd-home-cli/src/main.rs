@@ -1,17 +1,378 @@
-use anyhow::Result;
-use std::fs::File;
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, ValueEnum};
+use image::{codecs::png::PngEncoder, ImageEncoder};
+use serde::Deserialize;
+use std::{
+    fs,
+    io::{self, Cursor, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use epd_home::screen::Screen;
+use epd_home::{
+    calendar::Ics,
+    gtfs::GtfsRealtime,
+    home_assistant::HomeAssistant,
+    frame::{Frame, Rotation},
+    ink::Colors,
+    layout::Layout,
+    night::QuietHours,
+    raw::{BitOrder, RawOptions},
+    screen::Screen,
+};
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Bmp,
+    /// Greyscale, or RGB for colour panels
+    Png,
+    Pbm,
+    Pgm,
+    /// Packed 1 bit per pixel for uploading to a panel. By default most significant bit first, 1 is
+    /// white, and rows padded to whole bytes. For colour panels, as their controllers take it
+    Raw,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        Self::from_str(&ext, true).ok()
+    }
+}
+
+/// Screen options, which can be given as flags or in a TOML config file. Keys in the file are
+/// the flags' names in snake_case, as in the web server's screen config. Flags take precedence
+/// over the file, and `--no-…` flags turn off what the file turns on.
+#[derive(Args, Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct Options {
+    /// Latitude of the weather location
+    #[arg(long, allow_hyphen_values = true)]
+    lat: Option<f64>,
+
+    /// Longitude of the weather location
+    #[arg(long, allow_hyphen_values = true)]
+    lon: Option<f64>,
+
+    /// IANA timezone, e.g. Pacific/Auckland
+    #[arg(long)]
+    timezone: Option<String>,
+
+    /// Stop codes to show arrivals for, comma separated
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    stops: Vec<String>,
+
+    /// Error diffusion kernel, e.g. stucki (default), floyd, atkinson, or ordered dithering with
+    /// bayer2, bayer4, bayer8 or blue-noise, which is stable between partial refreshes
+    #[arg(long)]
+    dither: Option<String>,
+
+    /// Scan alternate rows in opposite directions when diffusing error
+    #[arg(long, num_args = 0, default_missing_value = "true", overrides_with = "no_serpentine")]
+    serpentine: Option<bool>,
+
+    /// Don't scan alternate rows in opposite directions, even if the config file does
+    #[arg(long, overrides_with = "serpentine")]
+    #[serde(skip)]
+    no_serpentine: bool,
+
+    /// Diffuse error in linear light, which keeps the brightness of photos and gradients
+    #[arg(long, num_args = 0, default_missing_value = "true", overrides_with = "no_linear_light")]
+    linear_light: Option<bool>,
+
+    /// Diffuse error in sRGB, even if the config file says linear light
+    #[arg(long, overrides_with = "linear_light")]
+    #[serde(skip)]
+    no_linear_light: bool,
+
+    /// Colours of the panel: bw (default), bwr, bwy, acep, spectra6, gray2 or gray4
+    #[arg(long)]
+    colors: Option<Colors>,
+
+    /// Width of the panel in pixels, if it's not 800
+    #[arg(long, requires = "height")]
+    width: Option<u32>,
+
+    /// Height of the panel in pixels, if it's not 480
+    #[arg(long, requires = "width")]
+    height: Option<u32>,
+
+    /// Degrees clockwise to turn the layout onto the panel: 0, 90, 180 or 270
+    #[arg(long)]
+    rotation: Option<Rotation>,
+
+    /// MiniJinja template to lay the screen out with instead of the built-in one, see
+    /// epd-home/templates/home.svg
+    #[arg(long)]
+    template: Option<PathBuf>,
+
+    /// Widgets on a grid instead of the built-in layout, as a `[layout]` table in the config
+    /// file, see epd_home::layout
+    #[arg(skip)]
+    layout: Option<Layout>,
+
+    /// When to show the night frame instead, as a `quiet_hours` table in the config file, see
+    /// epd_home::night
+    #[arg(skip)]
+    quiet_hours: Option<QuietHours>,
+
+    /// iCalendar files or URLs to show today's and tomorrow's events from, comma separated
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    calendars: Vec<String>,
+
+    /// Home Assistant URL, e.g. http://homeassistant.local:8123
+    #[arg(long, requires_all = ["home_assistant_token", "entities"])]
+    home_assistant_url: Option<String>,
+
+    /// Long-lived access token for Home Assistant
+    #[arg(long, requires = "home_assistant_url")]
+    home_assistant_token: Option<String>,
+
+    /// Home Assistant entity IDs to show, comma separated
+    #[arg(long, value_delimiter = ',', requires = "home_assistant_url")]
+    #[serde(default)]
+    entities: Vec<String>,
+
+    /// Static GTFS zip to use for arrivals instead of next-at-api
+    #[arg(long, requires = "gtfs_trip_updates")]
+    gtfs_schedule: Option<PathBuf>,
+
+    /// GTFS-Realtime TripUpdates URL or file, used with --gtfs-schedule
+    #[arg(long, requires = "gtfs_schedule")]
+    gtfs_trip_updates: Option<String>,
+
+    /// Where to write the image, or - for stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Image format. Defaults to the output file's extension, or bmp
+    #[arg(short, long)]
+    format: Option<Format>,
+
+    /// Put the leftmost pixel in the least significant bit of raw output
+    #[arg(long, num_args = 0, default_missing_value = "true", overrides_with = "no_lsb_first")]
+    lsb_first: Option<bool>,
+
+    /// Put the leftmost pixel in the most significant bit of raw output, even if the config file doesn't
+    #[arg(long, overrides_with = "lsb_first")]
+    #[serde(skip)]
+    no_lsb_first: bool,
+
+    /// Write black as 1 in raw output
+    #[arg(long, num_args = 0, default_missing_value = "true", overrides_with = "no_invert")]
+    invert: Option<bool>,
+
+    /// Write black as 0 in raw output, even if the config file inverts it
+    #[arg(long, overrides_with = "invert")]
+    #[serde(skip)]
+    no_invert: bool,
+
+    /// Pad each row of raw output to a whole byte, which is the default
+    #[arg(long, num_args = 0, default_missing_value = "true", overrides_with = "no_row_padding")]
+    row_padding: Option<bool>,
+
+    /// Don't pad each row of raw output to a whole byte
+    #[arg(long, overrides_with = "row_padding")]
+    #[serde(skip)]
+    no_row_padding: bool,
+}
+
+/// A flag given on the command line as `--flag` or `--no-flag`, if either.
+fn flag(on: Option<bool>, off: bool) -> Option<bool> {
+    match off {
+        true => Some(false),
+        false => on,
+    }
+}
+
+impl Options {
+    /// Fills in anything not set here from `other`.
+    fn or(self, other: Options) -> Options {
+        Options {
+            lat: self.lat.or(other.lat),
+            lon: self.lon.or(other.lon),
+            timezone: self.timezone.or(other.timezone),
+            stops: if self.stops.is_empty() { other.stops } else { self.stops },
+            dither: self.dither.or(other.dither),
+            serpentine: flag(self.serpentine, self.no_serpentine).or(other.serpentine),
+            no_serpentine: false,
+            linear_light: flag(self.linear_light, self.no_linear_light).or(other.linear_light),
+            no_linear_light: false,
+            colors: self.colors.or(other.colors),
+            width: self.width.or(other.width),
+            height: self.height.or(other.height),
+            rotation: self.rotation.or(other.rotation),
+            template: self.template.or(other.template),
+            layout: self.layout.or(other.layout),
+            quiet_hours: self.quiet_hours.or(other.quiet_hours),
+            calendars: if self.calendars.is_empty() { other.calendars } else { self.calendars },
+            home_assistant_url: self.home_assistant_url.or(other.home_assistant_url),
+            home_assistant_token: self.home_assistant_token.or(other.home_assistant_token),
+            entities: if self.entities.is_empty() { other.entities } else { self.entities },
+            gtfs_schedule: self.gtfs_schedule.or(other.gtfs_schedule),
+            gtfs_trip_updates: self.gtfs_trip_updates.or(other.gtfs_trip_updates),
+            output: self.output.or(other.output),
+            format: self.format.or(other.format),
+            lsb_first: flag(self.lsb_first, self.no_lsb_first).or(other.lsb_first),
+            no_lsb_first: false,
+            invert: flag(self.invert, self.no_invert).or(other.invert),
+            no_invert: false,
+            row_padding: flag(self.row_padding, self.no_row_padding).or(other.row_padding),
+            no_row_padding: false,
+        }
+    }
+
+    fn raw_options(&self) -> RawOptions {
+        let lsb_first = flag(self.lsb_first, self.no_lsb_first).unwrap_or(false);
+        RawOptions {
+            bit_order: if lsb_first { BitOrder::Lsb } else { BitOrder::Msb },
+            invert: flag(self.invert, self.no_invert).unwrap_or(false),
+            pad_rows: flag(self.row_padding, self.no_row_padding).unwrap_or(true),
+        }
+    }
+}
+
+/// Render the home screen to an image file.
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    /// TOML file with default options
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Render the layout with dummy data, without fetching anything
+    #[arg(long, conflicts_with = "error")]
+    placeholder: bool,
+
+    /// Render the error screen
+    #[arg(long)]
+    error: bool,
+
+    #[command(flatten)]
+    options: Options,
+}
+
+fn encode(frame: Frame, format: Format, raw_options: &RawOptions) -> Result<Vec<u8>> {
+    if frame.colors() != Colors::BlackWhite && matches!(format, Format::Bmp | Format::Pbm) {
+        bail!("{:?} output is black and white only, use png or raw with --colors", format);
+    }
+
+    let data = match format {
+        Format::Bmp => frame.to_bmp()?,
+        Format::Png => {
+            let (pixels, color_type) = match frame.colors() {
+                Colors::BlackWhite | Colors::Grayscale(_) => {
+                    (frame.to_luma8(), image::ExtendedColorType::L8)
+                }
+                _ => (frame.to_rgb8(), image::ExtendedColorType::Rgb8),
+            };
+            let mut buff = Cursor::new(Vec::new());
+            PngEncoder::new(&mut buff).write_image(&pixels, frame.width(), frame.height(), color_type)?;
+            buff.into_inner()
+        }
+        Format::Pbm => frame.to_pbm()?,
+        Format::Pgm => frame.to_pgm(),
+        Format::Raw => frame.to_raw(raw_options),
+    };
+
+    Ok(data)
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
 
-    let mut out_file = File::create("home.bmp")?;
+    let cli = Cli::parse();
+
+    let options = match &cli.config {
+        Some(path) => {
+            let data = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let file_options: Options = toml::from_str(&data)
+                .with_context(|| format!("Invalid config {}", path.display()))?;
+            cli.options.or(file_options)
+        }
+        None => cli.options,
+    };
+    if let Some(layout) = &options.layout {
+        layout.validate().context("Invalid layout")?;
+    }
+
+    let raw_options = options.raw_options();
+    let colors = options.colors.unwrap_or_default();
+    let output = options.output.unwrap_or_else(|| "home.bmp".into());
+    let format = options
+        .format
+        .or_else(|| Format::from_path(&output))
+        .unwrap_or(Format::Bmp);
+
+    // What the panel can show, for real screens and placeholders alike
+    let for_panel = |screen: Screen| -> Result<Screen> {
+        let mut screen = screen
+            .with_colors(colors)
+            .with_rotation(options.rotation.unwrap_or_default())
+            .with_serpentine(flag(options.serpentine, options.no_serpentine).unwrap_or(false))
+            .with_linear_light(flag(options.linear_light, options.no_linear_light).unwrap_or(false));
+        if let Some(dither) = &options.dither {
+            screen = screen.with_dither(dither)?;
+        }
+        if let Some(template) = &options.template {
+            screen = screen.with_template(template);
+        }
+        if let Some(layout) = &options.layout {
+            screen = screen.with_layout(layout.clone());
+        }
+        match (options.width, options.height) {
+            (Some(width), Some(height)) => Ok(screen.with_size(width, height)?),
+            (None, None) => Ok(screen),
+            _ => bail!("--width and --height must be given together"),
+        }
+    };
+
+    let frame = if cli.placeholder || cli.error {
+        let timezone = options.timezone.as_deref().unwrap_or("UTC");
+        let screen = for_panel(Screen::new(0.0, 0.0, timezone, &[])?)?;
+        if cli.error {
+            screen.render_error().await?
+        } else {
+            screen.render_placeholder().await?
+        }
+    } else {
+        let (Some(lat), Some(lon), Some(timezone)) = (options.lat, options.lon, &options.timezone)
+        else {
+            bail!("--lat, --lon and --timezone are required unless using --placeholder or --error");
+        };
+
+        let stops = options.stops.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+        let mut screen = Screen::new(lat, lon, timezone, &stops)?;
+        if let (Some(schedule), Some(trip_updates)) =
+            (&options.gtfs_schedule, &options.gtfs_trip_updates)
+        {
+            screen = screen.with_arrivals_source(Arc::new(GtfsRealtime::new(schedule, trip_updates)?));
+        }
+        for calendar in &options.calendars {
+            screen = screen.with_calendar(Arc::new(Ics::new(calendar)));
+        }
+        if let (Some(url), Some(token)) = (&options.home_assistant_url, &options.home_assistant_token) {
+            let entities = options.entities.iter().map(|id| id.as_str()).collect::<Vec<_>>();
+            screen = screen.with_entities(Arc::new(HomeAssistant::new(url, token)), &entities);
+        }
+        if let Some(quiet_hours) = options.quiet_hours {
+            screen = screen.with_quiet_hours(quiet_hours);
+        }
+        for_panel(screen)?.render().await?
+    };
+
+    let data = encode(frame, format, &raw_options)?;
 
-    Screen::new(-36.75, 174.625, "Pacific/Auckland", "3889")?
-        .render(&mut out_file)
-        .await?;
+    if output == Path::new("-") {
+        io::stdout().write_all(&data)?;
+    } else {
+        fs::write(&output, data).with_context(|| format!("Failed to write {}", output.display()))?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,333 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use epd_home::{frame::Rotation, ink::Colors, layout::Layout, night::QuietHours};
+use serde::Deserialize;
+
+use crate::{Error, Result};
+
+/// Named screens loaded from the file at `CONFIG_PATH` when the server starts.
+///
+/// ```toml
+/// [screens.hallway]
+/// lat = -36.85
+/// lon = 174.76
+/// timezone = "Pacific/Auckland"
+/// stops = ["1000", "7036"]
+/// dither = "atkinson"
+/// serpentine = true
+/// colors = "bwr"
+/// width = 800
+/// height = 480
+/// rotation = 90
+/// template = "/templates/hallway.svg"
+/// format = "bmp"
+/// quiet_hours = { start = "23:00", end = "06:00" }
+///
+/// [screens.hallway.gtfs]
+/// schedule = "/data/gtfs.zip"
+/// trip_updates = "https://api.example.com/realtime/tripupdates"
+/// headers = { "X-Api-Key" = "..." }
+///
+/// [screens.kitchen]
+/// lat = -36.85
+/// lon = 174.76
+/// timezone = "Pacific/Auckland"
+/// calendars = ["webcal://calendar.example.com/family.ics", "/data/school.ics"]
+///
+/// [screens.kitchen.layout]
+/// columns = 2
+/// rows = 2
+/// widgets = [
+///     { kind = "clock", column = 0, row = 0 },
+///     { kind = "weather", column = 1, row = 0 },
+///     { kind = "calendar", column = 0, row = 1 },
+///     { kind = "entities", column = 1, row = 1 },
+/// ]
+///
+/// [screens.kitchen.home_assistant]
+/// url = "http://homeassistant.local:8123"
+/// token = "..."
+/// entities = ["sensor.lounge_temperature", "binary_sensor.back_door"]
+/// ```
+///
+/// Unknown keys are rejected, so a misspelled one doesn't quietly fall back to the default.
+///
+/// With an `[mqtt]` table, screens listing `devices` are also pushed to a broker each time
+/// they're rendered, see [`crate::mqtt`]:
+///
+/// ```toml
+/// [mqtt]
+/// host = "broker.local"
+///
+/// [screens.hallway]
+/// devices = ["hallway-panel"]
+/// ```
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    #[serde(default)]
+    pub(crate) screens: HashMap<String, ScreenConfig>,
+    pub(crate) mqtt: Option<MqttConfig>,
+}
+
+/// The broker to publish frames to.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MqttConfig {
+    pub(crate) host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub(crate) port: u16,
+    pub(crate) username: Option<String>,
+    pub(crate) password: Option<String>,
+    #[serde(default = "default_mqtt_client_id")]
+    pub(crate) client_id: String,
+    /// Frames go to `<topic_prefix>/<device>/frame`
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub(crate) topic_prefix: String,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "epd-home".into()
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "epd-home".into()
+}
+
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Format {
+    #[default]
+    Bmp,
+    Qoi,
+    /// Greyscale, or RGB for colour screens
+    Png,
+    Pgm,
+    /// Packed 1 bit per pixel, see [`epd_home::raw`]
+    Raw,
+    /// Only what changed since the last frame sent to a device, see [`epd_home::partial`]
+    Diff,
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bmp" => Ok(Format::Bmp),
+            "qoi" => Ok(Format::Qoi),
+            "png" => Ok(Format::Png),
+            "pgm" => Ok(Format::Pgm),
+            "raw" => Ok(Format::Raw),
+            "diff" => Ok(Format::Diff),
+            _ => Err(Error::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+/// Entity states from Home Assistant.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct HomeAssistantConfig {
+    /// e.g. `http://homeassistant.local:8123`
+    pub(crate) url: String,
+    /// A long-lived access token
+    pub(crate) token: String,
+    pub(crate) entities: Vec<String>,
+}
+
+/// Arrivals from a GTFS timetable and GTFS-Realtime feed instead of next-at-api.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct GtfsConfig {
+    pub(crate) schedule: String,
+    pub(crate) trip_updates: String,
+    #[serde(default)]
+    pub(crate) headers: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ScreenConfig {
+    pub(crate) lat: f64,
+    pub(crate) lon: f64,
+    pub(crate) timezone: String,
+    #[serde(default)]
+    pub(crate) stops: Vec<String>,
+    /// An error diffusion kernel such as atkinson, or bayer2, bayer4, bayer8 or blue-noise for
+    /// ordered dithering, which doesn't shimmer between partial refreshes
+    pub(crate) dither: Option<String>,
+    /// Scan alternate rows in opposite directions when diffusing error
+    #[serde(default)]
+    pub(crate) serpentine: bool,
+    /// Diffuse error in linear light, which keeps the brightness of photos and gradients
+    #[serde(default)]
+    pub(crate) linear_light: bool,
+    /// For colour and greyscale panels, e.g. bwr, bwy, acep, spectra6, gray2 or gray4
+    #[serde(default)]
+    pub(crate) colors: Colors,
+    /// The panel's size in pixels, if it's not 800 x 480. Both or neither must be given.
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    /// Degrees clockwise to turn the layout onto the panel: 0, 90, 180 or 270
+    #[serde(default)]
+    pub(crate) rotation: Rotation,
+    /// A MiniJinja template to lay the screen out with instead of the built-in one, read
+    /// again on every render. Only settable here, not from the query string.
+    pub(crate) template: Option<PathBuf>,
+    /// Widgets on a grid instead of the built-in layout, see [`epd_home::layout`]. Only
+    /// settable here, not from the query string.
+    pub(crate) layout: Option<Layout>,
+    /// iCalendar files or URLs for a calendar widget or template. Only settable here, not from
+    /// the query string.
+    #[serde(default)]
+    pub(crate) calendars: Vec<String>,
+    /// When to show the night frame instead, in the screen's timezone, see
+    /// [`epd_home::night`]. Only settable here, not from the query string.
+    pub(crate) quiet_hours: Option<QuietHours>,
+    #[serde(default)]
+    pub(crate) format: Format,
+    pub(crate) gtfs: Option<GtfsConfig>,
+    pub(crate) home_assistant: Option<HomeAssistantConfig>,
+    /// IDs of devices showing this screen, to push frames to over MQTT
+    #[serde(default)]
+    pub(crate) devices: Vec<String>,
+}
+
+/// Query parameters for a screen. For named screens, these override the configured values.
+#[derive(Deserialize)]
+pub(crate) struct HomeScreenOptions {
+    lat: Option<f64>,
+    lon: Option<f64>,
+    timezone: Option<String>,
+    stop_code: Option<String>,
+    dither: Option<String>,
+    serpentine: Option<bool>,
+    linear_light: Option<bool>,
+    colors: Option<Colors>,
+    width: Option<u32>,
+    height: Option<u32>,
+    rotation: Option<Rotation>,
+}
+
+/// Query parameters for diff output.
+#[derive(Deserialize)]
+pub(crate) struct DiffOptions {
+    /// Any ID unique to the device, to compare against the last frame it was sent.
+    pub(crate) device: Option<String>,
+    /// Send the whole frame, e.g. after the device has restarted.
+    #[serde(default)]
+    pub(crate) full: bool,
+}
+
+impl HomeScreenOptions {
+    /// Whether nothing is overridden, so a configured screen can be served as pre-rendered.
+    pub(crate) fn is_empty(&self) -> bool {
+        let HomeScreenOptions {
+            lat,
+            lon,
+            timezone,
+            stop_code,
+            dither,
+            serpentine,
+            linear_light,
+            colors,
+            width,
+            height,
+            rotation,
+        } = self;
+        lat.is_none()
+            && lon.is_none()
+            && timezone.is_none()
+            && stop_code.is_none()
+            && dither.is_none()
+            && serpentine.is_none()
+            && linear_light.is_none()
+            && colors.is_none()
+            && width.is_none()
+            && height.is_none()
+            && rotation.is_none()
+    }
+
+    fn stops(&self) -> Option<Vec<String>> {
+        self.stop_code
+            .as_ref()
+            .map(|codes| codes.split(',').map(|s| s.to_string()).collect())
+    }
+
+    /// A screen described entirely by the query string.
+    pub(crate) fn to_config(&self) -> Result<ScreenConfig> {
+        let config = ScreenConfig {
+            lat: self.lat.ok_or(Error::MissingOption("lat"))?,
+            lon: self.lon.ok_or(Error::MissingOption("lon"))?,
+            timezone: self.timezone.clone().ok_or(Error::MissingOption("timezone"))?,
+            stops: self.stops().unwrap_or_default(),
+            dither: self.dither.clone(),
+            serpentine: self.serpentine.unwrap_or_default(),
+            linear_light: self.linear_light.unwrap_or_default(),
+            colors: self.colors.unwrap_or_default(),
+            width: self.width,
+            height: self.height,
+            rotation: self.rotation.unwrap_or_default(),
+            template: None,
+            layout: None,
+            quiet_hours: None,
+            calendars: vec![],
+            format: Format::default(),
+            gtfs: None,
+            home_assistant: None,
+            devices: vec![],
+        };
+        Ok(config)
+    }
+
+    pub(crate) fn apply_to(&self, config: &ScreenConfig) -> ScreenConfig {
+        ScreenConfig {
+            lat: self.lat.unwrap_or(config.lat),
+            lon: self.lon.unwrap_or(config.lon),
+            timezone: self.timezone.clone().unwrap_or_else(|| config.timezone.clone()),
+            stops: self.stops().unwrap_or_else(|| config.stops.clone()),
+            dither: self.dither.clone().or_else(|| config.dither.clone()),
+            serpentine: self.serpentine.unwrap_or(config.serpentine),
+            linear_light: self.linear_light.unwrap_or(config.linear_light),
+            colors: self.colors.unwrap_or(config.colors),
+            width: self.width.or(config.width),
+            height: self.height.or(config.height),
+            rotation: self.rotation.unwrap_or(config.rotation),
+            ..config.clone()
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path)?;
+        Ok(toml::from_str(&data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let screen = "[screens.hallway]\nlat = 51.5\nlon = -0.1\ntimezone = \"Europe/London\"\n";
+        assert!(toml::from_str::<Config>(screen).is_ok());
+
+        let misspelled = format!("{screen}quiet-hours = {{ start = \"23:00\", end = \"06:00\" }}\n");
+        assert!(toml::from_str::<Config>(&misspelled).is_err());
+
+        let mqtt = format!("{screen}[mqtt]\nhost = \"broker.local\"\ntopic-prefix = \"panels\"\n");
+        assert!(toml::from_str::<Config>(&mqtt).is_err());
+    }
+}
@@ -1,9 +1,42 @@
-use std::{env, io::Cursor};
+mod config;
+mod mqtt;
+mod scheduler;
 
-use actix_web::{get, middleware, web, App, HttpResponse, HttpServer, Responder, ResponseError};
-use epd_home::screen::{self, Screen};
-use image::{codecs::qoi::QoiEncoder, ImageEncoder};
-use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env,
+    io::Cursor,
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    get,
+    http::header::{HeaderName, HeaderValue},
+    middleware, web, App, HttpResponse, HttpServer, Responder, ResponseError,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use config::{Config, DiffOptions, Format, HomeScreenOptions, ScreenConfig};
+use epd_home::{
+    cache::SourceCache,
+    calendar::Ics,
+    gtfs::GtfsRealtime,
+    home_assistant::HomeAssistant,
+    frame::Frame,
+    ink::Colors,
+    partial,
+    raw::RawOptions,
+    refresh,
+    screen::{self, Rendered, Screen},
+    transport::ArrivalsSource,
+};
+use image::{
+    codecs::{png::PngEncoder, qoi::QoiEncoder},
+    ImageEncoder,
+};
+use serde::Serialize;
 
 #[derive(thiserror::Error, Debug)]
 enum Error {
@@ -13,8 +46,20 @@ enum Error {
     #[error("Failed to decode image")]
     Image(#[from] image::error::ImageError),
 
-    #[error("Failed to encode image")]
-    Bitmap(#[from] bmp_monochrome::BmpError),
+    #[error("Missing option: {0}")]
+    MissingOption(&'static str),
+
+    #[error("Unknown screen: {0}")]
+    UnknownScreen(String),
+
+    #[error("Unknown format: {0}")]
+    UnknownFormat(String),
+
+    #[error("Failed to read config")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid config: {0}")]
+    Config(#[from] toml::de::Error),
 }
 
 impl ResponseError for Error {
@@ -27,10 +72,16 @@ impl ResponseError for Error {
             Screen(err) => {
                 use screen::Error::*;
                 match err {
-                    InvalidTimezone => HttpResponse::BadRequest().into(),
+                    InvalidTimezone | UnknownDither(_) | InvalidRotation(_) | InvalidSize(..)
+                    | UnknownColors(_) | BlackWhiteOnly(_) => {
+                        HttpResponse::BadRequest().into()
+                    }
+                    Template(_) | InvalidSvg(_) | Render(_) | InvalidLayout(_) => HttpResponse::InternalServerError().into(),
                     _ => HttpResponse::BadGateway().into(),
                 }
             },
+            MissingOption(_) => HttpResponse::BadRequest().into(),
+            UnknownScreen(_) | UnknownFormat(_) => HttpResponse::NotFound().into(),
             _ => HttpResponse::InternalServerError().into(),
         }
     }
@@ -38,87 +89,589 @@ impl ResponseError for Error {
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// A configured screen, with its arrivals source if it doesn't use the default.
+struct NamedScreen {
+    config: ScreenConfig,
+    arrivals: Option<Arc<dyn ArrivalsSource>>,
+    /// The scheduler's latest render and when it finished, see [`scheduler`].
+    latest: RwLock<Option<(Instant, Rendered)>>,
+}
+
+impl NamedScreen {
+    /// The scheduler's latest render, unless it's too old to serve.
+    fn latest(&self) -> Option<Rendered> {
+        let latest = self.latest.read().unwrap();
+        let (rendered_at, rendered) = latest.as_ref()?;
+        let stale = scheduler::is_stale(*rendered_at, rendered, render_interval());
+        (!stale).then(|| rendered.clone())
+    }
+}
+
+type Screens = HashMap<String, NamedScreen>;
+
+/// How many devices [`LastFrames`] remembers. Device ids come from clients, so this stops them
+/// using up memory with made-up ones.
+const MAX_DEVICES: usize = 256;
+
+/// The last frame sent to each device, for diff output. Once it's remembering [`MAX_DEVICES`],
+/// the device that asked least recently is forgotten and gets a full frame next time.
+#[derive(Default)]
+struct LastFrames {
+    frames: HashMap<String, (u64, Frame)>,
+    /// Counts requests, to find the device that asked least recently
+    requests: u64,
+}
+
+impl LastFrames {
+    fn get(&self, device: &str) -> Option<&Frame> {
+        self.frames.get(device).map(|(_, frame)| frame)
+    }
+
+    fn insert(&mut self, device: &str, frame: Frame) {
+        self.requests += 1;
+        self.frames.insert(device.to_string(), (self.requests, frame));
+        if self.frames.len() > MAX_DEVICES {
+            let oldest = self
+                .frames
+                .iter()
+                .min_by_key(|(_, (asked, _))| asked)
+                .map(|(device, _)| device.clone());
+            self.frames.remove(&oldest.unwrap());
+        }
+    }
+}
+
+/// When a device should next ask for a screen, for battery-powered panels that deep-sleep
+/// between frames. Sent as headers with every image, or on its own as JSON.
+#[derive(Serialize)]
+struct RefreshHint {
+    /// Seconds to sleep, sent as `Refresh-After`
+    refresh_after: i64,
+    /// RFC 3339, sent as `X-Next-Refresh`
+    next_refresh: String,
+}
+
+impl RefreshHint {
+    fn new(next_refresh: DateTime<Tz>) -> Self {
+        let back_off = chrono::Duration::seconds(render_interval().as_secs() as i64);
+        let (next_refresh, refresh_after) = refresh::hint(next_refresh, Utc::now(), back_off);
+        RefreshHint {
+            refresh_after,
+            next_refresh: next_refresh.to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+
+    fn add_headers(&self, response: &mut HttpResponse) {
+        let headers = response.headers_mut();
+        headers.insert(
+            HeaderName::from_static("refresh-after"),
+            HeaderValue::from(self.refresh_after),
+        );
+        if let Ok(value) = HeaderValue::from_str(&self.next_refresh) {
+            headers.insert(HeaderName::from_static("x-next-refresh"), value);
+        }
+    }
+}
+
 #[get("/ok")]
 async fn ok() -> impl Responder {
     HttpResponse::Ok()
 }
 
-#[derive(Deserialize)]
-struct HomeScreenOptions {
-    lat: f64,
-    lon: f64,
-    timezone: String,
-    stop_code: String,
-}
+fn build_screen(
+    config: &ScreenConfig,
+    cache: Arc<SourceCache>,
+    arrivals: Option<&Arc<dyn ArrivalsSource>>,
+) -> Result<Screen> {
+    let stop_codes_ref = config.stops.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+
+    let mut screen = Screen::new(config.lat, config.lon, &config.timezone, &stop_codes_ref)?.with_cache(cache);
+    if let Some(source) = arrivals {
+        screen = screen.with_arrivals_source(source.clone());
+    }
+    if let Some(dither) = &config.dither {
+        screen = screen.with_dither(dither)?;
+    }
+    screen = screen
+        .with_serpentine(config.serpentine)
+        .with_linear_light(config.linear_light)
+        .with_colors(config.colors)
+        .with_rotation(config.rotation);
+    if let Some(template) = &config.template {
+        screen = screen.with_template(template);
+    }
+    if let Some(layout) = &config.layout {
+        screen = screen.with_layout(layout.clone());
+    }
+    if let Some(quiet_hours) = config.quiet_hours {
+        screen = screen.with_quiet_hours(quiet_hours);
+    }
+    for calendar in &config.calendars {
+        screen = screen.with_calendar(Arc::new(Ics::new(calendar)));
+    }
+    if let Some(home_assistant) = &config.home_assistant {
+        let entities = home_assistant.entities.iter().map(|id| id.as_str()).collect::<Vec<_>>();
+        let source = HomeAssistant::new(&home_assistant.url, &home_assistant.token);
+        screen = screen.with_entities(Arc::new(source), &entities);
+    }
+    match (config.width, config.height) {
+        (Some(width), Some(height)) => screen = screen.with_size(width, height)?,
+        (Some(_), None) => return Err(Error::MissingOption("height")),
+        (None, Some(_)) => return Err(Error::MissingOption("width")),
+        (None, None) => (),
+    }
 
-async fn render(options: web::Query<HomeScreenOptions>) -> Result<Vec<Vec<bool>>> {
-    let stop_codes: Vec<String> = options.stop_code.split(',').map(|s| s.to_string()).collect();
-    let stop_codes_ref = stop_codes.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+    Ok(screen)
+}
 
-    let img = Screen::new(options.lat, options.lon, &options.timezone, &stop_codes_ref)?
-        .render()
-        .await?;
+async fn render(
+    config: &ScreenConfig,
+    cache: Arc<SourceCache>,
+    arrivals: Option<&Arc<dyn ArrivalsSource>>,
+) -> Result<Rendered> {
+    let rendered = build_screen(config, cache, arrivals)?.render_with_refresh().await?;
 
-    Ok(img)
+    Ok(rendered)
 }
 
-#[get("/home.bmp")]
-async fn get_home_screen_bmp(options: web::Query<HomeScreenOptions>) -> Result<impl Responder> {
-    
-    let data = render(options).await?;
+fn encode_bmp(frame: Frame) -> Result<HttpResponse> {
+    let response = HttpResponse::Ok()
+        .content_type("image/bmp")
+        .body(frame.to_bmp()?);
+
+    Ok(response)
+}
 
+fn encode_qoi(frame: Frame) -> Result<HttpResponse> {
     let mut buff = Cursor::new(Vec::new());
-    bmp_monochrome::Bmp::new(data)?.write(&mut buff)?;
+    QoiEncoder::new(&mut buff).write_image(
+        &frame.to_rgb8(),
+        frame.width(),
+        frame.height(),
+        image::ExtendedColorType::Rgb8,
+    )?;
 
     let response = HttpResponse::Ok()
-        .content_type("image/bmp")
+        .content_type("image/qoi")
         .body(buff.into_inner());
 
     Ok(response)
 }
 
-#[get("/home.qoi")]
-async fn get_home_screen_qoi(options: web::Query<HomeScreenOptions>) -> Result<impl Responder> {
-    
-    let img = render(options).await?;
-
-    let w = img[0].len() as u32;
-    let h = img.len() as u32;
-
-    let data: Vec<u8> = img.into_iter().flatten().flat_map(|px| {
-        if px {
-            [0, 0, 0]
-        } else {
-            [255, 255, 255]
-        }
-    })
-    .collect();
+fn encode_png(frame: Frame) -> Result<HttpResponse> {
+    let (data, color_type) = match frame.colors() {
+        Colors::BlackWhite | Colors::Grayscale(_) => (frame.to_luma8(), image::ExtendedColorType::L8),
+        _ => (frame.to_rgb8(), image::ExtendedColorType::Rgb8),
+    };
 
     let mut buff = Cursor::new(Vec::new());
-    QoiEncoder::new(&mut buff)
-        .write_image(&data, w, h, image::ExtendedColorType::Rgb8)?;
+    PngEncoder::new(&mut buff).write_image(&data, frame.width(), frame.height(), color_type)?;
 
     let response = HttpResponse::Ok()
-        .content_type("image/qoi")
+        .content_type("image/png")
         .body(buff.into_inner());
 
     Ok(response)
 }
 
+fn encode_pgm(frame: Frame) -> Result<HttpResponse> {
+    let response = HttpResponse::Ok()
+        .content_type("image/x-portable-graymap")
+        .body(frame.to_pgm());
+
+    Ok(response)
+}
+
+/// Colour screens are packed as their controllers take them, see [`epd_home::raw::pack_for_panel`].
+fn encode_raw(frame: Frame, options: &RawOptions) -> Result<HttpResponse> {
+    let response = HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .body(frame.to_raw(options));
+
+    Ok(response)
+}
+
+fn encode_diff(
+    frame: Frame,
+    raw_options: &RawOptions,
+    diff_options: &DiffOptions,
+    last_frames: &Mutex<LastFrames>,
+) -> Result<HttpResponse> {
+    let device = diff_options
+        .device
+        .as_ref()
+        .ok_or(Error::MissingOption("device"))?;
+
+    let mut last_frames = last_frames.lock().unwrap();
+    let prev = match diff_options.full {
+        true => None,
+        false => last_frames.get(device),
+    };
+    let data = partial::encode_diff(prev, &frame, raw_options);
+    last_frames.insert(device, frame);
+
+    let response = HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .body(data);
+
+    Ok(response)
+}
+
+/// Encodes the frame with `encode_frame`, adding its refresh hint.
+fn encode_with_refresh(
+    rendered: Rendered,
+    encode_frame: impl FnOnce(Frame) -> Result<HttpResponse>,
+) -> Result<HttpResponse> {
+    let hint = RefreshHint::new(rendered.next_refresh);
+    let mut response = encode_frame(rendered.frame)?;
+    hint.add_headers(&mut response);
+
+    Ok(response)
+}
+
+fn encode(
+    rendered: Rendered,
+    format: Format,
+    raw_options: &RawOptions,
+    diff_options: &DiffOptions,
+    last_frames: &Mutex<LastFrames>,
+) -> Result<HttpResponse> {
+    encode_with_refresh(rendered, |frame| match format {
+        Format::Bmp => encode_bmp(frame),
+        Format::Qoi => encode_qoi(frame),
+        Format::Png => encode_png(frame),
+        Format::Pgm => encode_pgm(frame),
+        Format::Raw => encode_raw(frame, raw_options),
+        Format::Diff => encode_diff(frame, raw_options, diff_options, last_frames),
+    })
+}
+
+#[get("/home.bmp")]
+async fn get_home_screen_bmp(
+    options: web::Query<HomeScreenOptions>,
+    cache: web::Data<SourceCache>,
+) -> Result<impl Responder> {
+    let rendered = render(&options.to_config()?, cache.into_inner(), None).await?;
+
+    encode_with_refresh(rendered, encode_bmp)
+}
+
+#[get("/home.qoi")]
+async fn get_home_screen_qoi(
+    options: web::Query<HomeScreenOptions>,
+    cache: web::Data<SourceCache>,
+) -> Result<impl Responder> {
+    let rendered = render(&options.to_config()?, cache.into_inner(), None).await?;
+
+    encode_with_refresh(rendered, encode_qoi)
+}
+
+#[get("/home.png")]
+async fn get_home_screen_png(
+    options: web::Query<HomeScreenOptions>,
+    cache: web::Data<SourceCache>,
+) -> Result<impl Responder> {
+    let rendered = render(&options.to_config()?, cache.into_inner(), None).await?;
+
+    encode_with_refresh(rendered, encode_png)
+}
+
+#[get("/home.pgm")]
+async fn get_home_screen_pgm(
+    options: web::Query<HomeScreenOptions>,
+    cache: web::Data<SourceCache>,
+) -> Result<impl Responder> {
+    let rendered = render(&options.to_config()?, cache.into_inner(), None).await?;
+
+    encode_with_refresh(rendered, encode_pgm)
+}
+
+#[get("/home.raw")]
+async fn get_home_screen_raw(
+    options: web::Query<HomeScreenOptions>,
+    raw_options: web::Query<RawOptions>,
+    cache: web::Data<SourceCache>,
+) -> Result<impl Responder> {
+    let rendered = render(&options.to_config()?, cache.into_inner(), None).await?;
+
+    encode_with_refresh(rendered, |frame| encode_raw(frame, &raw_options))
+}
+
+#[get("/home.diff")]
+async fn get_home_screen_diff(
+    options: web::Query<HomeScreenOptions>,
+    raw_options: web::Query<RawOptions>,
+    diff_options: web::Query<DiffOptions>,
+    cache: web::Data<SourceCache>,
+    last_frames: web::Data<Mutex<LastFrames>>,
+) -> Result<impl Responder> {
+    let rendered = render(&options.to_config()?, cache.into_inner(), None).await?;
+
+    encode_with_refresh(rendered, |frame| {
+        encode_diff(frame, &raw_options, &diff_options, &last_frames)
+    })
+}
+
+#[get("/home.json")]
+async fn get_home_screen_refresh(
+    options: web::Query<HomeScreenOptions>,
+    cache: web::Data<SourceCache>,
+) -> Result<impl Responder> {
+    let screen = build_screen(&options.to_config()?, cache.into_inner(), None)?;
+
+    Ok(web::Json(RefreshHint::new(screen.next_refresh().await)))
+}
+
+/// Returns a configured screen with the format to encode it in. Without overrides, that's the
+/// latest frame from the scheduler, unless it hasn't rendered the screen recently.
+async fn render_named_screen(
+    name: &str,
+    format: Option<&str>,
+    options: &HomeScreenOptions,
+    screens: &Screens,
+    cache: web::Data<SourceCache>,
+) -> Result<(Rendered, Format)> {
+    let screen = screens
+        .get(name)
+        .ok_or_else(|| Error::UnknownScreen(name.to_string()))?;
+    let format = match format {
+        Some(format) => format.parse()?,
+        None => screen.config.format,
+    };
+
+    if options.is_empty() {
+        if let Some(rendered) = screen.latest() {
+            return Ok((rendered, format));
+        }
+    }
+
+    let config = options.apply_to(&screen.config);
+    let rendered = render(&config, cache.into_inner(), screen.arrivals.as_ref()).await?;
+
+    Ok((rendered, format))
+}
+
+#[get("/screens/{name:[^/.]+}")]
+async fn get_named_screen(
+    name: web::Path<String>,
+    options: web::Query<HomeScreenOptions>,
+    raw_options: web::Query<RawOptions>,
+    diff_options: web::Query<DiffOptions>,
+    screens: web::Data<Screens>,
+    cache: web::Data<SourceCache>,
+    last_frames: web::Data<Mutex<LastFrames>>,
+) -> Result<impl Responder> {
+    let (rendered, format) = render_named_screen(&name, None, &options, &screens, cache).await?;
+
+    encode(rendered, format, &raw_options, &diff_options, &last_frames)
+}
+
+#[get("/screens/{name:[^/.]+}.{format}")]
+async fn get_named_screen_with_format(
+    path: web::Path<(String, String)>,
+    options: web::Query<HomeScreenOptions>,
+    raw_options: web::Query<RawOptions>,
+    diff_options: web::Query<DiffOptions>,
+    screens: web::Data<Screens>,
+    cache: web::Data<SourceCache>,
+    last_frames: web::Data<Mutex<LastFrames>>,
+) -> Result<impl Responder> {
+    let (name, format) = path.into_inner();
+    let (rendered, format) = render_named_screen(&name, Some(&format), &options, &screens, cache).await?;
+
+    encode(rendered, format, &raw_options, &diff_options, &last_frames)
+}
+
+/// Only the refresh hint, for devices that can't read the image's headers. This has to be
+/// registered before `get_named_screen_with_format`, which would take `json` for a format.
+#[get("/screens/{name:[^/.]+}.json")]
+async fn get_named_screen_refresh(
+    name: web::Path<String>,
+    options: web::Query<HomeScreenOptions>,
+    screens: web::Data<Screens>,
+    cache: web::Data<SourceCache>,
+) -> Result<impl Responder> {
+    let screen = screens
+        .get(name.as_str())
+        .ok_or_else(|| Error::UnknownScreen(name.to_string()))?;
+
+    // Even a stale frame's hint will do, as one that's passed backs off instead of fetching
+    // everything on the screen to work it out again
+    if options.is_empty() {
+        if let Some((_, rendered)) = screen.latest.read().unwrap().as_ref() {
+            return Ok(web::Json(RefreshHint::new(rendered.next_refresh)));
+        }
+    }
+
+    let config = options.apply_to(&screen.config);
+    let next_refresh = build_screen(&config, cache.into_inner(), screen.arrivals.as_ref())?
+        .next_refresh()
+        .await;
+
+    Ok(web::Json(RefreshHint::new(next_refresh)))
+}
+
+fn load_screens(config: Config) -> Result<Screens> {
+    config
+        .screens
+        .into_iter()
+        .map(|(name, config)| {
+            if let Some(layout) = &config.layout {
+                layout.validate()?;
+            }
+            let arrivals = match &config.gtfs {
+                Some(gtfs) => {
+                    let source = gtfs.headers.iter().fold(
+                        GtfsRealtime::new(&gtfs.schedule, &gtfs.trip_updates)?,
+                        |source, (name, value)| source.with_header(name, value),
+                    );
+                    Some(Arc::new(source) as Arc<dyn ArrivalsSource>)
+                }
+                None => None,
+            };
+            Ok((
+                name,
+                NamedScreen {
+                    config,
+                    arrivals,
+                    latest: RwLock::default(),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// How often the scheduler renders configured screens, see [`scheduler`].
+fn render_interval() -> Duration {
+    ttl_from_env("RENDER_INTERVAL", 60)
+}
+
+/// Reads a duration in seconds from the environment.
+fn ttl_from_env(key: &str, default_secs: u64) -> Duration {
+    let secs = env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default_secs);
+    Duration::from_secs(secs)
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
 
     let listen_address = env::var("LISTEN_ADDRESS").unwrap_or("127.0.0.1:8080".to_string());
 
-    HttpServer::new(|| {
+    let cache = web::Data::new(SourceCache::new(
+        ttl_from_env("WEATHER_CACHE_TTL", 600),
+        ttl_from_env("ARRIVALS_CACHE_TTL", 30),
+    ));
+
+    let config = match env::var("CONFIG_PATH") {
+        Ok(path) => Config::load(Path::new(&path)),
+        Err(_) => Ok(Config::default()),
+    };
+    let config = config.map_err(|err| std::io::Error::other(err.to_string()))?;
+    let mqtt = config.mqtt.clone();
+    let screens = load_screens(config).map_err(|err| std::io::Error::other(err.to_string()))?;
+    let screens = web::Data::new(screens);
+
+    if !screens.is_empty() {
+        scheduler::render_screens(
+            render_interval(),
+            screens.clone(),
+            cache.clone(),
+            mqtt.as_ref().map(mqtt::Publisher::connect),
+        );
+    }
+    let last_frames = web::Data::new(Mutex::new(LastFrames::default()));
+
+    HttpServer::new(move || {
         App::new()
+            .app_data(cache.clone())
+            .app_data(screens.clone())
+            .app_data(last_frames.clone())
             .wrap(middleware::Compress::default())
             .service(ok)
             .service(get_home_screen_bmp)
             .service(get_home_screen_qoi)
+            .service(get_home_screen_png)
+            .service(get_home_screen_pgm)
+            .service(get_home_screen_raw)
+            .service(get_home_screen_diff)
+            .service(get_home_screen_refresh)
+            .service(get_named_screen)
+            .service(get_named_screen_refresh)
+            .service(get_named_screen_with_format)
     })
     .bind(listen_address)?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn serves_named_screens_from_the_latest_render() {
+        let config: ScreenConfig = toml::from_str("lat = 51.5\nlon = -0.1\ntimezone = \"Europe/London\"").unwrap();
+        // Smaller than the screen would render at, so it can't have been rendered on request
+        let latest = Rendered {
+            frame: Frame::new(10, 10, Colors::BlackWhite),
+            next_refresh: Utc::now().with_timezone(&"Europe/London".parse().unwrap()),
+        };
+        let screen = NamedScreen {
+            config,
+            arrivals: None,
+            latest: RwLock::new(Some((Instant::now(), latest))),
+        };
+        let screens = Screens::from([("hallway".to_string(), screen)]);
+        let cache = web::Data::new(SourceCache::new(Duration::ZERO, Duration::ZERO));
+        let options = web::Query::<HomeScreenOptions>::from_query("").unwrap();
+
+        let (rendered, format) = render_named_screen("hallway", None, &options, &screens, cache)
+            .await
+            .unwrap();
+        assert_eq!((rendered.frame.width(), rendered.frame.height()), (10, 10));
+        assert!(format == Format::Bmp);
+    }
+
+    #[test]
+    fn rejects_overlapping_widgets_on_load() {
+        let config: Config = toml::from_str(
+            r#"
+            [screens.kitchen]
+            lat = 51.5
+            lon = -0.1
+            timezone = "Europe/London"
+
+            [screens.kitchen.layout]
+            columns = 2
+            rows = 1
+            widgets = [
+                { kind = "clock", column = 0, row = 0, column_span = 2 },
+                { kind = "weather", column = 1, row = 0 },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let loaded = load_screens(config);
+        assert!(matches!(loaded, Err(Error::Screen(screen::Error::InvalidLayout(_)))));
+    }
+
+    #[test]
+    fn forgets_the_device_that_asked_least_recently() {
+        let mut last_frames = LastFrames::default();
+        for device in 0..MAX_DEVICES {
+            last_frames.insert(&device.to_string(), Frame::new(1, 1, Colors::BlackWhite));
+        }
+        last_frames.insert("0", Frame::new(1, 1, Colors::BlackWhite));
+        last_frames.insert("new", Frame::new(1, 1, Colors::BlackWhite));
+
+        assert_eq!(last_frames.frames.len(), MAX_DEVICES);
+        assert!(last_frames.get("0").is_some());
+        assert!(last_frames.get("1").is_none());
+        assert!(last_frames.get("new").is_some());
+    }
+}
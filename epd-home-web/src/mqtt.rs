@@ -0,0 +1,208 @@
+//! Pushing frames to devices over MQTT, so they don't have to poll.
+//!
+//! Each time the scheduler renders a configured screen with `devices`, its frame is packed as
+//! for `.raw` output with the default options. When a device's frame changes, it's published to
+//! `<topic_prefix>/<device>/frame`, then its SHA-256 as lowercase hex to
+//! `<topic_prefix>/<device>/hash`. Both are retained, so a device waking from deep sleep gets the
+//! latest frame as soon as it subscribes, and can check the hash against the frame it's showing
+//! before downloading anything. Everything is sent again after the connection to the broker is
+//! re-established, in case it restarted without keeping retained messages.
+//!
+//! Publishing only queues messages for the connection, so rendering carries on while the broker
+//! is down. Once the queue is full, frames are dropped and tried again on the next render.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix_web::rt;
+use epd_home::{frame::Frame, raw::RawOptions, screen::MAX_PANEL_SIZE};
+use rumqttc::{AsyncClient, ClientError, Event, MqttOptions, Packet, QoS};
+use sha2::{Digest, Sha256};
+
+use crate::config::{MqttConfig, ScreenConfig};
+
+/// The largest packet sent or received: the largest frame, at 4 bits per pixel, with room for
+/// the topic. Frames are 48000 bytes for an 800 x 480 black and white panel, so the 10 KiB
+/// default is too small.
+const MAX_PACKET_SIZE: usize = (MAX_PANEL_SIZE as usize).pow(2) / 2 + (64 << 10);
+
+/// How many messages wait for the connection before more are dropped.
+const QUEUE_SIZE: usize = 16;
+
+fn content_hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Where messages go, so publishing can be tested without a broker.
+pub(crate) trait Client {
+    /// Queues a retained message to be sent at least once, failing if the queue is full.
+    fn try_publish_retained(&self, topic: String, payload: Vec<u8>) -> Result<(), ClientError>;
+}
+
+impl Client for AsyncClient {
+    fn try_publish_retained(&self, topic: String, payload: Vec<u8>) -> Result<(), ClientError> {
+        self.try_publish(topic, QoS::AtLeastOnce, true, payload)
+    }
+}
+
+pub(crate) struct Publisher<C = AsyncClient> {
+    client: C,
+    topic_prefix: String,
+    /// The hash last published for each device, cleared when the broker reconnects
+    published: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl Publisher {
+    /// Connects in the background. Connection errors are logged and retried.
+    pub(crate) fn connect(config: &MqttConfig) -> Self {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options
+            .set_keep_alive(Duration::from_secs(30))
+            .set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            options.set_credentials(username, password);
+        }
+        let (client, mut eventloop) = AsyncClient::new(options, QUEUE_SIZE);
+        let publisher = Publisher::new(client, &config.topic_prefix);
+
+        let published = publisher.published.clone();
+        rt::spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => published.lock().unwrap().clear(),
+                    Ok(_) => (),
+                    Err(err) => {
+                        log::error!("MQTT connection failed: {}", err);
+                        rt::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        });
+
+        publisher
+    }
+}
+
+impl<C: Client> Publisher<C> {
+    fn new(client: C, topic_prefix: &str) -> Self {
+        Publisher {
+            client,
+            topic_prefix: topic_prefix.to_string(),
+            published: Arc::default(),
+        }
+    }
+
+    /// Publishes `frame` to each of the screen's devices that doesn't have it yet, without
+    /// waiting for the broker.
+    pub(crate) fn publish(&self, screen: &ScreenConfig, frame: &Frame) {
+        if screen.devices.is_empty() {
+            return;
+        }
+        let data = frame.to_raw(&RawOptions::default());
+        let hash = content_hash(&data);
+
+        for device in &screen.devices {
+            if self.published.lock().unwrap().get(device) == Some(&hash) {
+                continue;
+            }
+            let topic = |name: &str| format!("{}/{}/{}", self.topic_prefix, device, name);
+            let sent = self
+                .client
+                .try_publish_retained(topic("frame"), data.clone())
+                .and_then(|()| self.client.try_publish_retained(topic("hash"), hash.clone().into_bytes()));
+            match sent {
+                Ok(()) => {
+                    log::debug!("Published {} to {}", hash, device);
+                    self.published.lock().unwrap().insert(device.clone(), hash.clone());
+                }
+                Err(err) => log::error!("Failed to publish to {}: {}", device, err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_as_lowercase_hex() {
+        assert_eq!(
+            content_hash(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[derive(Default)]
+    struct FakeClient {
+        sent: Mutex<Vec<String>>,
+        /// As when the broker is down and the queue has filled up
+        full: Mutex<bool>,
+    }
+
+    impl Client for FakeClient {
+        fn try_publish_retained(&self, topic: String, payload: Vec<u8>) -> Result<(), ClientError> {
+            if *self.full.lock().unwrap() {
+                let publish = rumqttc::Publish::new(topic, QoS::AtLeastOnce, payload);
+                return Err(ClientError::TryRequest(rumqttc::Request::Publish(publish)));
+            }
+            self.sent.lock().unwrap().push(topic);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn publishes_only_changed_frames() {
+        let screen: ScreenConfig = toml::from_str(
+            "lat = 51.5\nlon = -0.1\ntimezone = \"Europe/London\"\ndevices = [\"hall\", \"stairs\"]",
+        )
+        .unwrap();
+        let publisher = Publisher::new(FakeClient::default(), "epd-home");
+        let sent = || std::mem::take(&mut *publisher.client.sent.lock().unwrap());
+        let white = Frame::new(8, 8, Default::default());
+        let mut black = white.clone();
+        black.set(0, 0, epd_home::ink::Ink::Black);
+
+        publisher.publish(&screen, &white);
+        assert_eq!(
+            sent(),
+            ["epd-home/hall/frame", "epd-home/hall/hash", "epd-home/stairs/frame", "epd-home/stairs/hash"]
+        );
+
+        publisher.publish(&screen, &white);
+        assert!(sent().is_empty());
+
+        publisher.publish(&screen, &black);
+        assert_eq!(sent().len(), 4);
+
+        // As after the broker reconnects
+        publisher.published.lock().unwrap().clear();
+        publisher.publish(&screen, &black);
+        assert_eq!(sent().len(), 4);
+    }
+
+    #[test]
+    fn tries_again_after_a_full_queue() {
+        let screen: ScreenConfig =
+            toml::from_str("lat = 51.5\nlon = -0.1\ntimezone = \"Europe/London\"\ndevices = [\"hall\"]").unwrap();
+        let publisher = Publisher::new(FakeClient::default(), "epd-home");
+        let frame = Frame::new(8, 8, Default::default());
+
+        *publisher.client.full.lock().unwrap() = true;
+        publisher.publish(&screen, &frame);
+        assert!(publisher.published.lock().unwrap().is_empty());
+
+        *publisher.client.full.lock().unwrap() = false;
+        publisher.publish(&screen, &frame);
+        assert_eq!(*publisher.client.sent.lock().unwrap(), ["epd-home/hall/frame", "epd-home/hall/hash"]);
+    }
+
+    #[test]
+    fn fits_the_largest_frame_in_a_packet() {
+        let frame = Frame::new(MAX_PANEL_SIZE, MAX_PANEL_SIZE, epd_home::ink::Colors::Grayscale(4));
+        assert!(frame.to_raw(&RawOptions::default()).len() < MAX_PACKET_SIZE - 1024);
+    }
+}
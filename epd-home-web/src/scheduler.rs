@@ -0,0 +1,146 @@
+//! Rendering configured screens in the background, so requests for them return straight away
+//! instead of waiting on weather and arrivals.
+//!
+//! Screens are rendered when the server starts, then every `RENDER_INTERVAL` seconds (60 by
+//! default), timed from the epoch so a 60 second interval renders just after each minute starts
+//! and the clock is right for the whole minute. A frame's refresh hint is never before the next
+//! render, as devices asking sooner would get the same frame again. During a screen's quiet
+//! hours, its night frame is rendered once and left until they end, though it's still offered
+//! to MQTT devices every interval in case the broker has reconnected and they need it again.
+//!
+//! Each screen renders in its own task, so one with a slow source doesn't hold the others up, and
+//! a render that takes longer than the interval is given up on. A frame that hasn't been replaced
+//! for two intervals, and whose refresh hint has passed, isn't served any more, so requests render
+//! the screen themselves, or fail.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{rt, web};
+use chrono::{DateTime, Utc};
+use epd_home::{cache::SourceCache, screen::Rendered};
+
+use crate::{mqtt::Publisher, render, Error, NamedScreen, Screens};
+
+/// How long from `now` until the next whole multiple of `interval` since the epoch.
+fn until_next(interval: Duration, now: SystemTime) -> Duration {
+    let interval = interval.as_millis().max(1);
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    Duration::from_millis((interval - since_epoch % interval) as u64)
+}
+
+/// Whether a frame rendered at `rendered_at` is too old to serve. Night frames are kept until
+/// their quiet hours end, which is their refresh hint.
+pub(crate) fn is_stale(rendered_at: Instant, rendered: &Rendered, interval: Duration) -> bool {
+    rendered_at.elapsed() > interval * 2 && Utc::now() >= rendered.next_refresh
+}
+
+/// Whether the screen's latest frame is the night frame for the quiet hours it's in now.
+fn showing_night_frame(screen: &NamedScreen) -> bool {
+    let latest = screen.latest.read().unwrap();
+    let (Some(quiet_hours), Some((_, latest))) = (screen.config.quiet_hours, &*latest) else {
+        return false;
+    };
+    let now = Utc::now().with_timezone(&latest.next_refresh.timezone());
+    quiet_hours.ends_at(now) == Some(latest.next_refresh)
+}
+
+/// Starts rendering every configured screen until the server stops, publishing them with
+/// `publisher` if there is one.
+pub(crate) fn render_screens(
+    interval: Duration,
+    screens: web::Data<Screens>,
+    cache: web::Data<SourceCache>,
+    publisher: Option<Publisher>,
+) {
+    let publisher = Arc::new(publisher);
+    for name in screens.keys() {
+        rt::spawn(render_screen(
+            interval,
+            name.clone(),
+            screens.clone(),
+            cache.clone(),
+            publisher.clone(),
+        ));
+    }
+}
+
+/// Renders one configured screen every interval.
+async fn render_screen(
+    interval: Duration,
+    name: String,
+    screens: web::Data<Screens>,
+    cache: web::Data<SourceCache>,
+    publisher: Arc<Option<Publisher>>,
+) {
+    loop {
+        let next_render = SystemTime::now() + until_next(interval, SystemTime::now());
+        let next_render = DateTime::<Utc>::from(next_render);
+
+        let screen = &screens[&name];
+        if showing_night_frame(screen) {
+            // Only goes out to devices that don't have it, such as after the broker reconnects
+            if let (Some(publisher), Some((_, latest))) = (&*publisher, &*screen.latest.read().unwrap()) {
+                publisher.publish(&screen.config, &latest.frame);
+            }
+        } else {
+            // In a task of its own, so a panic while rendering only loses this frame
+            let rendering = rt::spawn({
+                let (screens, cache, name) = (screens.clone(), cache.clone(), name.clone());
+                async move {
+                    let screen = &screens[&name];
+                    let rendering = render(&screen.config, cache.into_inner(), screen.arrivals.as_ref());
+                    rt::time::timeout(interval, rendering)
+                        .await
+                        .unwrap_or(Err(Error::Screen(epd_home::screen::Error::Timeout(interval))))
+                }
+            });
+            match rendering.await {
+                Ok(Ok(mut rendered)) => {
+                    if let Some(publisher) = &*publisher {
+                        publisher.publish(&screen.config, &rendered.frame);
+                    }
+                    let timezone = rendered.next_refresh.timezone();
+                    rendered.next_refresh = rendered.next_refresh.max(next_render.with_timezone(&timezone));
+                    *screen.latest.write().unwrap() = Some((Instant::now(), rendered));
+                }
+                Ok(Err(err)) => log::error!("Failed to render {}: {}", name, err),
+                Err(err) => log::error!("Rendering {} panicked: {}", name, err),
+            }
+        }
+
+        rt::time::sleep(until_next(interval, SystemTime::now())).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligns_to_the_interval() {
+        let minute = Duration::from_secs(60);
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(until_next(minute, at(1_700_000_015)), Duration::from_secs(25));
+        // Exactly on the boundary waits for the next one rather than rendering twice
+        assert_eq!(until_next(minute, at(1_700_000_040)), minute);
+    }
+
+    #[test]
+    fn drops_frames_after_two_intervals() {
+        let second = Duration::from_secs(1);
+        let rendered = |next_refresh: DateTime<Utc>| Rendered {
+            frame: epd_home::frame::Frame::new(1, 1, Default::default()),
+            next_refresh: next_refresh.with_timezone(&chrono_tz::UTC),
+        };
+        let passed = rendered(Utc::now());
+        assert!(!is_stale(Instant::now(), &passed, second));
+        assert!(is_stale(Instant::now() - second * 3, &passed, second));
+
+        // A night frame is kept until morning
+        let night = rendered(Utc::now() + chrono::Duration::hours(8));
+        assert!(!is_stale(Instant::now() - second * 3600, &night, second));
+    }
+}
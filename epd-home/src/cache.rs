@@ -0,0 +1,247 @@
+use std::{collections::HashMap, future::Future, hash::Hash, sync::Arc, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    screen::{Error, Result},
+    transport::RouteArrivals,
+    weather::Weather,
+};
+
+/// How long a key can go unrequested before its value is dropped, so locations and stops that
+/// no screen asks for any more don't pile up.
+const UNUSED_LIMIT: chrono::Duration = chrono::Duration::days(1);
+
+struct Entry<T> {
+    value: T,
+    fetched_at: DateTime<Utc>,
+}
+
+/// How long a fetch can take before it's given up on and the last good value served instead,
+/// unless set with [`SourceCache::with_fetch_timeout`].
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The value for one key. Fetches hold its lock, so concurrent misses wait for the first one to
+/// finish instead of each fetching.
+struct Slot<T> {
+    requested_at: DateTime<Utc>,
+    entry: Arc<futures::lock::Mutex<Option<Entry<T>>>>,
+}
+
+type Slots<K, T> = Mutex<HashMap<K, Slot<T>>>;
+
+/// A value from a data source, and when it was fetched if it's being served stale.
+pub(crate) struct Fetched<T> {
+    pub value: T,
+    pub stale_since: Option<DateTime<Utc>>,
+}
+
+impl<T> Fetched<T> {
+    pub fn fresh(value: T) -> Self {
+        Self {
+            value,
+            stale_since: None,
+        }
+    }
+}
+
+/// Remembers the last good response from each data source, keyed by the source's
+/// [`cache_key`](crate::transport::ArrivalsSource::cache_key) and the location or stop code.
+///
+/// Share one between screens (e.g. with an `Arc`) so that panels polling the same location
+/// don't each hit the upstream APIs, even if they use different sources. Values younger than the
+/// TTL are served without fetching, and if a fetch fails or times out the last good value is
+/// served instead, however old it is. Keys that haven't been asked for in a day are forgotten.
+pub struct SourceCache {
+    weather_ttl: Duration,
+    arrivals_ttl: Duration,
+    fetch_timeout: Duration,
+    weather: Slots<(String, u64, u64), Weather>,
+    arrivals: Slots<(String, String), Vec<RouteArrivals>>,
+}
+
+impl SourceCache {
+    pub fn new(weather_ttl: Duration, arrivals_ttl: Duration) -> Self {
+        Self {
+            weather_ttl,
+            arrivals_ttl,
+            fetch_timeout: FETCH_TIMEOUT,
+            weather: Mutex::new(HashMap::new()),
+            arrivals: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Give up on fetches after `timeout` instead of 30 seconds. Other screens asking for the
+    /// same key wait on the fetch, so this bounds how long a hung upstream holds them all up.
+    pub fn with_fetch_timeout(mut self, timeout: Duration) -> Self {
+        self.fetch_timeout = timeout;
+        self
+    }
+
+    pub(crate) async fn weather(
+        &self,
+        source: String,
+        latitude: f64,
+        longitude: f64,
+        fetch: impl Future<Output = Result<Weather>>,
+    ) -> Result<Fetched<Weather>> {
+        let key = (source, latitude.to_bits(), longitude.to_bits());
+        get_or_fetch(&self.weather, key, self.weather_ttl, self.fetch_timeout, fetch).await
+    }
+
+    pub(crate) async fn arrivals(
+        &self,
+        source: String,
+        stop_code: &str,
+        fetch: impl Future<Output = Result<Vec<RouteArrivals>>>,
+    ) -> Result<Fetched<Vec<RouteArrivals>>> {
+        let key = (source, stop_code.to_string());
+        get_or_fetch(&self.arrivals, key, self.arrivals_ttl, self.fetch_timeout, fetch).await
+    }
+}
+
+/// The lock on `key`'s value, dropping any keys that have gone unused.
+fn slot<K: Eq + Hash, T>(
+    slots: &Slots<K, T>,
+    key: K,
+) -> Arc<futures::lock::Mutex<Option<Entry<T>>>> {
+    let now = Utc::now();
+    let mut slots = slots.lock().unwrap();
+    slots.retain(|_, slot| now - slot.requested_at < UNUSED_LIMIT);
+    let slot = slots.entry(key).or_insert_with(|| Slot {
+        requested_at: now,
+        entry: Arc::default(),
+    });
+    slot.requested_at = now;
+    slot.entry.clone()
+}
+
+async fn get_or_fetch<K: Eq + Hash, T: Clone>(
+    slots: &Slots<K, T>,
+    key: K,
+    ttl: Duration,
+    timeout: Duration,
+    fetch: impl Future<Output = Result<T>>,
+) -> Result<Fetched<T>> {
+    let slot = slot(slots, key);
+    let mut entry = slot.lock().await;
+
+    if let Some(entry) = entry.as_ref() {
+        let is_fresh = (Utc::now() - entry.fetched_at)
+            .to_std()
+            .map_or(true, |age| age < ttl);
+        if is_fresh {
+            return Ok(Fetched::fresh(entry.value.clone()));
+        }
+    }
+
+    let fetched = tokio::time::timeout(timeout, fetch)
+        .await
+        .unwrap_or(Err(Error::Timeout(timeout)));
+    match fetched {
+        Ok(value) => {
+            *entry = Some(Entry {
+                value: value.clone(),
+                fetched_at: Utc::now(),
+            });
+            Ok(Fetched::fresh(value))
+        }
+        Err(err) => match entry.as_ref() {
+            Some(entry) => {
+                log::warn!("Serving data from {} after error: {}", entry.fetched_at, err);
+                Ok(Fetched {
+                    value: entry.value.clone(),
+                    stale_since: Some(entry.fetched_at),
+                })
+            }
+            None => Err(err),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arrivals(route: &str) -> Vec<RouteArrivals> {
+        vec![RouteArrivals {
+            route: route.into(),
+            headsign: "Britomart".into(),
+            arrival_times: vec![],
+        }]
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_last_good_value() {
+        let cache = SourceCache::new(Duration::ZERO, Duration::ZERO);
+
+        let fetched = cache.arrivals("next-at".into(), "1234", async { Ok(arrivals("NX1")) }).await.unwrap();
+        assert!(fetched.stale_since.is_none());
+
+        let fetched = cache
+            .arrivals("next-at".into(), "1234", async { Err(Error::MissingData("arrivals".into())) })
+            .await
+            .unwrap();
+        assert_eq!(fetched.value[0].route, "NX1");
+        assert!(fetched.stale_since.is_some());
+
+        let fetched = cache
+            .arrivals("next-at".into(), "5678", async { Err(Error::MissingData("arrivals".into())) })
+            .await;
+        assert!(fetched.is_err());
+    }
+
+    #[tokio::test]
+    async fn fetches_once_for_concurrent_misses() {
+        let cache = SourceCache::new(Duration::from_secs(60), Duration::from_secs(60));
+        let fetches = std::sync::atomic::AtomicUsize::new(0);
+        let fetch = || async {
+            fetches.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            Ok(arrivals("NX1"))
+        };
+
+        let (first, second) = tokio::join!(
+            cache.arrivals("next-at".into(), "1234", fetch()),
+            cache.arrivals("next-at".into(), "1234", fetch())
+        );
+        assert_eq!(first.unwrap().value[0].route, "NX1");
+        assert_eq!(second.unwrap().value[0].route, "NX1");
+        assert_eq!(fetches.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn falls_back_when_a_fetch_hangs() {
+        let cache = SourceCache::new(Duration::ZERO, Duration::ZERO).with_fetch_timeout(Duration::from_millis(10));
+        cache.arrivals("next-at".into(), "1234", async { Ok(arrivals("NX1")) }).await.unwrap();
+
+        let fetched = cache
+            .arrivals("next-at".into(), "1234", futures::future::pending())
+            .await
+            .unwrap();
+        assert_eq!(fetched.value[0].route, "NX1");
+        assert!(fetched.stale_since.is_some());
+
+        let fetched = cache.arrivals("next-at".into(), "5678", futures::future::pending()).await;
+        assert!(matches!(fetched, Err(Error::Timeout(_))));
+    }
+
+    #[tokio::test]
+    async fn keeps_sources_apart() {
+        let cache = SourceCache::new(Duration::from_secs(60), Duration::from_secs(60));
+        cache.arrivals("next-at".into(), "1234", async { Ok(arrivals("NX1")) }).await.unwrap();
+
+        let fetched = cache.arrivals("gtfs".into(), "1234", async { Ok(arrivals("70")) }).await.unwrap();
+        assert_eq!(fetched.value[0].route, "70");
+    }
+
+    #[test]
+    fn forgets_unused_keys() {
+        let slots = Mutex::new(HashMap::new());
+        slot::<_, ()>(&slots, "1234");
+        slots.lock().unwrap().get_mut("1234").unwrap().requested_at -= UNUSED_LIMIT;
+        slot::<_, ()>(&slots, "5678");
+        assert!(!slots.lock().unwrap().contains_key("1234"));
+        assert!(slots.lock().unwrap().contains_key("5678"));
+    }
+}
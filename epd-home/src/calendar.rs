@@ -0,0 +1,714 @@
+//! Events from [iCalendar](https://datatracker.ietf.org/doc/html/rfc5545) feeds, such as a
+//! calendar app's "secret address" or a CalDAV calendar's export URL.
+//!
+//! Recurring events are expanded from their `RRULE`, with `EXDATE`s skipped and single
+//! occurrences moved or cancelled by a `RECURRENCE-ID`. `TZID`s have to be IANA names, as Google
+//! and Apple calendars use; `VTIMEZONE` definitions are ignored, and times in an unknown zone are
+//! taken to be in the screen's.
+
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+
+use crate::screen::{local_time, Error, Result};
+
+/// One occurrence of an event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub summary: String,
+    pub start: DateTime<Tz>,
+    pub end: DateTime<Tz>,
+    /// Runs from midnight to midnight wherever the screen is, rather than at a set time.
+    pub all_day: bool,
+}
+
+/// Something that can list the events in a span of time.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait CalendarSource: Send + Sync {
+    /// Events overlapping `start..end`, in `start`'s timezone, with recurring events expanded.
+    async fn events(&self, start: DateTime<Tz>, end: DateTime<Tz>) -> Result<Vec<Event>>;
+}
+
+/// An iCalendar file, fetched again each time events are asked for.
+pub struct Ics {
+    location: String,
+}
+
+impl Ics {
+    /// `location` is either an `http(s)://` or `webcal://` URL, or a path to a `.ics` file.
+    /// Credentials for a private feed can go in the URL.
+    pub fn new(location: &str) -> Self {
+        Ics {
+            location: location.to_string(),
+        }
+    }
+
+    async fn fetch(&self) -> Result<String> {
+        let url = match self.location.strip_prefix("webcal://") {
+            Some(rest) => format!("https://{rest}"),
+            None => self.location.clone(),
+        };
+        if url.starts_with("http://") || url.starts_with("https://") {
+            Ok(reqwest::get(&url).await?.error_for_status()?.text().await?)
+        } else {
+            read_file(&url).await
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn read_file(path: &str) -> Result<String> {
+    Ok(tokio::fs::read_to_string(path).await?)
+}
+
+/// Workers have no filesystem to read from.
+#[cfg(target_arch = "wasm32")]
+async fn read_file(path: &str) -> Result<String> {
+    Err(Error::InvalidCalendar(format!("{path} isn't an http(s) or webcal URL")))
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl CalendarSource for Ics {
+    async fn events(&self, start: DateTime<Tz>, end: DateTime<Tz>) -> Result<Vec<Event>> {
+        let calendar = Calendar::parse(&self.fetch().await?)?;
+        Ok(calendar.events(start, end))
+    }
+}
+
+/// The events in an iCalendar file.
+pub struct Calendar {
+    events: Vec<VEvent>,
+}
+
+impl Calendar {
+    /// Events that can't be understood, like ones repeating by a rule that isn't supported, are
+    /// left out rather than losing the whole calendar, as are lines that aren't properties.
+    pub fn parse(ics: &str) -> Result<Self> {
+        let mut events = vec![];
+        let mut event: Option<Vec<Property>> = None;
+        // Components inside an event, like alarms, whose properties aren't the event's
+        let mut nested = 0;
+
+        for line in unfold(ics) {
+            let property = match Property::parse(&line) {
+                Ok(property) => property,
+                Err(err) => {
+                    log::warn!("Skipping line: {}", err);
+                    continue;
+                }
+            };
+            match (property.name.as_str(), property.value.to_ascii_uppercase().as_str()) {
+                ("BEGIN", "VEVENT") => event = Some(vec![]),
+                ("END", "VEVENT") => {
+                    if let Some(properties) = event.take() {
+                        let summary = properties
+                            .iter()
+                            .find(|property| property.name == "SUMMARY")
+                            .map(Property::text)
+                            .unwrap_or_default();
+                        match VEvent::from_properties(properties) {
+                            Ok(vevent) => events.push(vevent),
+                            Err(err) => log::warn!("Skipping event {:?}: {}", summary, err),
+                        }
+                    }
+                    nested = 0;
+                }
+                ("BEGIN", _) if event.is_some() => nested += 1,
+                ("END", _) if event.is_some() => nested -= 1,
+                _ => {
+                    if let Some(properties) = event.as_mut().filter(|_| nested == 0) {
+                        properties.push(property);
+                    }
+                }
+            }
+        }
+
+        Ok(Calendar { events })
+    }
+
+    /// Occurrences overlapping `start..end`, in `start`'s timezone, soonest first.
+    pub fn events(&self, start: DateTime<Tz>, end: DateTime<Tz>) -> Vec<Event> {
+        let tz = start.timezone();
+
+        // Occurrences of recurring events that have been moved or cancelled
+        let overridden: HashSet<(&str, DateTime<Tz>)> = self
+            .events
+            .iter()
+            .filter_map(|event| Some((event.uid.as_str(), event.recurrence_id?.resolve(&tz))))
+            .collect();
+
+        let mut events: Vec<Event> = self
+            .events
+            .iter()
+            .filter(|event| !event.cancelled)
+            .flat_map(|event| {
+                let starts = match (&event.rule, event.recurrence_id) {
+                    (Some(rule), None) => rule.expand(event, &tz, end),
+                    _ => vec![event.start],
+                };
+                starts
+                    .into_iter()
+                    .filter(|start| {
+                        let key = (event.uid.as_str(), start.resolve(&tz));
+                        event.rule.is_none() || !overridden.contains(&key)
+                    })
+                    .map(|start| event.occurrence(start, &tz))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|event| {
+                // An event with no duration still shows if it's at the very start
+                event.start < end && (event.end > start || event.start >= start)
+            })
+            .collect();
+        events.sort_by(|a, b| {
+            (a.start, !a.all_day, &a.summary).cmp(&(b.start, !b.all_day, &b.summary))
+        });
+        events
+    }
+}
+
+/// Joins lines folded onto the next with leading whitespace.
+fn unfold(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in ics.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ if line.trim().is_empty() => (),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// A content line, e.g. `DTSTART;TZID=Pacific/Auckland:20240415T090000`.
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Result<Self> {
+        // The value starts after the first colon that isn't in a quoted parameter
+        let mut quoted = false;
+        let split = line
+            .char_indices()
+            .find(|(_, c)| {
+                if *c == '"' {
+                    quoted = !quoted;
+                }
+                *c == ':' && !quoted
+            })
+            .map(|(i, _)| i)
+            .ok_or_else(|| Error::InvalidCalendar(format!("no value in {line}")))?;
+        let (head, value) = (&line[..split], &line[split + 1..]);
+
+        let mut parts = head.split(';');
+        let name = parts.next().unwrap_or_default().to_ascii_uppercase();
+        let params = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.to_ascii_uppercase(), value.trim_matches('"').to_string()))
+            .collect();
+
+        Ok(Property {
+            name,
+            params,
+            value: value.to_string(),
+        })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn timestamp(&self) -> Result<Timestamp> {
+        Timestamp::parse(&self.value, self.param("TZID"))
+    }
+
+    /// A TEXT value, with its escapes undone and newlines as spaces, since it's shown on one line.
+    fn text(&self) -> String {
+        let mut text = String::with_capacity(self.value.len());
+        let mut chars = self.value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('n' | 'N') => text.push(' '),
+                    Some(escaped) => text.push(escaped),
+                    None => (),
+                },
+                c => text.push(c),
+            }
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Zone {
+    Utc,
+    Named(Tz),
+    /// Wherever the screen is, for dates and times without a zone
+    Floating,
+}
+
+/// A date or time as written, so recurrences keep the same wall-clock time across DST changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timestamp {
+    local: NaiveDateTime,
+    zone: Zone,
+}
+
+impl Timestamp {
+    /// A `DATE` like `20240415`, or a `DATE-TIME` like `20240415T090000` or `20240414T210000Z`.
+    fn parse(value: &str, tzid: Option<&str>) -> Result<Self> {
+        let invalid = || Error::InvalidCalendar(format!("invalid date {value}"));
+
+        if value.len() == 8 {
+            let date = NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| invalid())?;
+            return Ok(Timestamp {
+                local: date.and_time(NaiveTime::MIN),
+                zone: Zone::Floating,
+            });
+        }
+
+        let (local, utc) = match value.strip_suffix(['Z', 'z']) {
+            Some(local) => (local, true),
+            None => (value, false),
+        };
+        let local = NaiveDateTime::parse_from_str(local, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+        let zone = match (utc, tzid) {
+            (true, _) => Zone::Utc,
+            (false, Some(tzid)) => match tzid.parse() {
+                Ok(tz) => Zone::Named(tz),
+                Err(_) => {
+                    log::warn!("Unknown calendar timezone {}, using the screen's", tzid);
+                    Zone::Floating
+                }
+            },
+            (false, None) => Zone::Floating,
+        };
+        Ok(Timestamp { local, zone })
+    }
+
+    fn resolve(&self, tz: &Tz) -> DateTime<Tz> {
+        match self.zone {
+            Zone::Utc => Utc.from_utc_datetime(&self.local).with_timezone(tz),
+            Zone::Named(zone) => local_time(&zone, self.local).with_timezone(tz),
+            Zone::Floating => local_time(tz, self.local),
+        }
+    }
+
+    fn with_date(self, date: NaiveDate) -> Self {
+        Timestamp {
+            local: date.and_time(self.local.time()),
+            ..self
+        }
+    }
+}
+
+struct VEvent {
+    uid: String,
+    summary: String,
+    start: Timestamp,
+    all_day: bool,
+    /// Wall-clock length, so an all-day event is still a day long when the clocks change
+    duration: Duration,
+    rule: Option<Rule>,
+    exdates: Vec<Timestamp>,
+    recurrence_id: Option<Timestamp>,
+    cancelled: bool,
+}
+
+impl VEvent {
+    fn from_properties(properties: Vec<Property>) -> Result<Self> {
+        let find = |name: &str| properties.iter().find(|property| property.name == name);
+
+        let start = find("DTSTART").ok_or_else(|| Error::InvalidCalendar("event without DTSTART".into()))?;
+        let all_day = start.param("VALUE") == Some("DATE") || start.value.len() == 8;
+        let start = start.timestamp()?;
+
+        let duration = match (find("DTEND"), find("DURATION")) {
+            (Some(end), _) => end.timestamp()?.local - start.local,
+            (None, Some(duration)) => parse_duration(&duration.value)?,
+            (None, None) if all_day => Duration::days(1),
+            (None, None) => Duration::zero(),
+        };
+
+        let exdates = properties
+            .iter()
+            .filter(|property| property.name == "EXDATE")
+            .flat_map(|property| {
+                property
+                    .value
+                    .split(',')
+                    .map(|value| Timestamp::parse(value, property.param("TZID")))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(VEvent {
+            uid: find("UID").map(|uid| uid.value.clone()).unwrap_or_default(),
+            summary: find("SUMMARY").map(Property::text).unwrap_or_default(),
+            start,
+            all_day,
+            duration,
+            rule: find("RRULE").map(|rule| Rule::parse(&rule.value)).transpose()?,
+            exdates,
+            recurrence_id: find("RECURRENCE-ID").map(Property::timestamp).transpose()?,
+            cancelled: find("STATUS").is_some_and(|status| status.value.eq_ignore_ascii_case("CANCELLED")),
+        })
+    }
+
+    fn occurrence(&self, start: Timestamp, tz: &Tz) -> Event {
+        let end = Timestamp {
+            local: start.local + self.duration,
+            ..start
+        };
+        Event {
+            summary: self.summary.clone(),
+            start: start.resolve(tz),
+            end: end.resolve(tz),
+            all_day: self.all_day,
+        }
+    }
+}
+
+/// `P1W`, `P1D`, `PT1H30M` and so on.
+fn parse_duration(value: &str) -> Result<Duration> {
+    let invalid = || Error::InvalidCalendar(format!("invalid duration {value}"));
+
+    let (sign, rest) = match value.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let rest = rest.strip_prefix('P').ok_or_else(invalid)?;
+
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in rest.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                number.push(c);
+                continue;
+            }
+            'T' => continue,
+            'W' => 7 * 24 * 3600,
+            'D' => 24 * 3600,
+            'H' => 3600,
+            'M' => 60,
+            'S' => 1,
+            _ => return Err(invalid()),
+        };
+        seconds += number.parse::<i64>().map_err(|_| invalid())? * unit;
+        number.clear();
+    }
+    Ok(Duration::seconds(sign * seconds))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// An `RRULE`. Rules more frequent than daily, and `BYSETPOS`, aren't supported.
+#[derive(Debug)]
+struct Rule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<Timestamp>,
+    by_month: Vec<u32>,
+    by_month_day: Vec<i32>,
+    /// Weekdays, with e.g. `2` for the second of the month or `-1` for the last
+    by_day: Vec<(Option<i32>, Weekday)>,
+}
+
+impl Rule {
+    fn parse(value: &str) -> Result<Self> {
+        let invalid = || Error::InvalidCalendar(format!("unsupported RRULE {value}"));
+
+        let mut rule = Rule {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_month: vec![],
+            by_month_day: vec![],
+            by_day: vec![],
+        };
+        let mut frequency = None;
+
+        for part in value.split(';') {
+            let Some((key, value)) = part.split_once('=') else {
+                continue;
+            };
+            let list = || value.split(',');
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(invalid()),
+                    })
+                }
+                "INTERVAL" => rule.interval = value.parse().map_err(|_| invalid())?,
+                "COUNT" => rule.count = Some(value.parse().map_err(|_| invalid())?),
+                "UNTIL" => rule.until = Some(Timestamp::parse(value, None)?),
+                "BYMONTH" => rule.by_month = list().map(str::parse).collect::<Result<_, _>>().map_err(|_| invalid())?,
+                "BYMONTHDAY" => {
+                    rule.by_month_day = list().map(str::parse).collect::<Result<_, _>>().map_err(|_| invalid())?
+                }
+                "BYDAY" => {
+                    rule.by_day = list()
+                        .map(|day| {
+                            let (ordinal, weekday) = day.split_at(day.len().saturating_sub(2));
+                            let weekday = parse_weekday(weekday).ok_or_else(invalid)?;
+                            let ordinal = match ordinal {
+                                "" => None,
+                                ordinal => Some(ordinal.trim_start_matches('+').parse().map_err(|_| invalid())?),
+                            };
+                            Ok((ordinal, weekday))
+                        })
+                        .collect::<Result<_>>()?
+                }
+                "BYSETPOS" | "BYHOUR" | "BYMINUTE" | "BYSECOND" | "BYWEEKNO" | "BYYEARDAY" => {
+                    return Err(invalid())
+                }
+                _ => (),
+            }
+        }
+
+        rule.frequency = frequency.ok_or_else(invalid)?;
+        rule.interval = rule.interval.max(1);
+        Ok(rule)
+    }
+
+    /// Start times of `event` from its first up to `end`, less its `EXDATE`s.
+    fn expand(&self, event: &VEvent, tz: &Tz, end: DateTime<Tz>) -> Vec<Timestamp> {
+        let first = event.start.local.date();
+        // Margin for the event's timezone being ahead of the screen's
+        let last = end.date_naive() + Duration::days(1);
+        let until = self.until.map(|until| until.resolve(tz));
+        let excluded: HashSet<DateTime<Tz>> =
+            event.exdates.iter().map(|exdate| exdate.resolve(tz)).collect();
+
+        let mut starts = vec![];
+        let mut count = 0;
+        for date in first.iter_days().take_while(|date| *date <= last) {
+            if date != first && !self.matches(date, first) {
+                continue;
+            }
+            let start = event.start.with_date(date);
+            let resolved = start.resolve(tz);
+            if until.is_some_and(|until| resolved > until) {
+                break;
+            }
+            // Excluded dates still count towards COUNT
+            count += 1;
+            if !excluded.contains(&resolved) {
+                starts.push(start);
+            }
+            if self.count.is_some_and(|max| count >= max) {
+                break;
+            }
+        }
+        starts
+    }
+
+    /// Whether `date` is an occurrence of a rule starting on `first`. Whatever the rule leaves
+    /// out comes from `first`, e.g. a monthly rule repeats on the same day of the month.
+    fn matches(&self, date: NaiveDate, first: NaiveDate) -> bool {
+        let interval = i64::from(self.interval);
+        let months = |date: NaiveDate| i64::from(date.year()) * 12 + i64::from(date.month0());
+        let in_period = match self.frequency {
+            Frequency::Daily => (date - first).num_days() % interval == 0,
+            Frequency::Weekly => {
+                let week_start = |date: NaiveDate| date - Duration::days(date.weekday().num_days_from_monday().into());
+                (week_start(date) - week_start(first)).num_weeks() % interval == 0
+            }
+            Frequency::Monthly => (months(date) - months(first)) % interval == 0,
+            Frequency::Yearly => i64::from(date.year() - first.year()) % interval == 0,
+        };
+        if !in_period || (!self.by_month.is_empty() && !self.by_month.contains(&date.month())) {
+            return false;
+        }
+
+        let month_days = days_in_month(date);
+        let month_day = self.by_month_day.iter().any(|&day| {
+            let day = if day < 0 { month_days as i32 + 1 + day } else { day };
+            day == date.day() as i32
+        });
+        // Ordinals count within the month, or the year for a yearly rule without BYMONTH
+        let (index, length) = match self.frequency {
+            Frequency::Yearly if self.by_month.is_empty() => (date.ordinal0(), days_in_year(date)),
+            _ => (date.day0(), month_days),
+        };
+        let nth = |ordinal: i32| {
+            ordinal == (index / 7 + 1) as i32 || ordinal == -(((length - 1 - index) / 7 + 1) as i32)
+        };
+        let weekday = self.by_day.iter().any(|&(ordinal, weekday)| {
+            weekday == date.weekday()
+                && match ordinal {
+                    Some(ordinal) => nth(ordinal),
+                    None => true,
+                }
+        });
+
+        match (self.by_month_day.is_empty(), self.by_day.is_empty()) {
+            (false, false) => month_day && weekday,
+            (false, true) => month_day,
+            (true, false) => weekday,
+            (true, true) => match self.frequency {
+                Frequency::Daily => true,
+                Frequency::Weekly => date.weekday() == first.weekday(),
+                Frequency::Monthly => date.day() == first.day(),
+                Frequency::Yearly if self.by_month.is_empty() => {
+                    (date.month(), date.day()) == (first.month(), first.day())
+                }
+                Frequency::Yearly => date.day() == first.day(),
+            },
+        }
+    }
+}
+
+/// The two-letter weekdays of `BYDAY`.
+fn parse_weekday(day: &str) -> Option<Weekday> {
+    Some(match day.to_ascii_uppercase().as_str() {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+fn days_in_month(date: NaiveDate) -> u32 {
+    let next_month = match date.month() {
+        12 => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1),
+        month => NaiveDate::from_ymd_opt(date.year(), month + 1, 1),
+    };
+    next_month.and_then(|next| next.pred_opt()).map_or(31, |last| last.day())
+}
+
+fn days_in_year(date: NaiveDate) -> u32 {
+    if date.leap_year() {
+        366
+    } else {
+        365
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/calendar/household.ics");
+
+    fn day(tz: &Tz, year: i32, month: u32, day: u32) -> Vec<(String, String)> {
+        let calendar = Calendar::parse(&std::fs::read_to_string(FIXTURE).unwrap()).unwrap();
+        let start = tz.with_ymd_and_hms(year, month, day, 0, 0, 0).unwrap();
+        calendar
+            .events(start, start + Duration::days(1))
+            .into_iter()
+            .map(|event| (event.start.format("%H:%M").to_string(), event.summary))
+            .collect()
+    }
+
+    fn events(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|(time, summary)| (time.to_string(), summary.to_string())).collect()
+    }
+
+    #[test]
+    fn expands_recurring_events() {
+        let tz = chrono_tz::Pacific::Auckland;
+        // Bins on the second Tuesday of the month, the weekly swim, and a call at 11pm in London
+        assert_eq!(
+            day(&tz, 2024, 4, 9),
+            events(&[
+                ("00:00", "Bins, recycling"),
+                ("07:00", "Swimming"),
+                ("10:00", "Call with London"),
+            ])
+        );
+        // The call keeps to London time across both countries' clock changes
+        assert_eq!(day(&tz, 2024, 3, 26), events(&[("12:00", "Call with London")]));
+        // The swim was skipped one week, when the call had finished its COUNT...
+        assert_eq!(day(&tz, 2024, 4, 16), events(&[]));
+        // ...and moved to the Wednesday the week after
+        assert_eq!(day(&tz, 2024, 4, 23), events(&[]));
+        assert_eq!(day(&tz, 2024, 4, 24), events(&[("18:00", "Swimming")]));
+        assert_eq!(day(&tz, 2024, 4, 30), events(&[("07:00", "Swimming")]));
+    }
+
+    #[test]
+    fn keeps_all_day_events_to_the_screens_days() {
+        let tz = chrono_tz::Europe::London;
+        // A yearly birthday, and a trip across three days ending on the 3rd
+        assert_eq!(day(&tz, 2025, 3, 1), events(&[("00:00", "Alex's birthday"), ("00:00", "Trip")]));
+        // Tuesday morning in Auckland is Monday evening here
+        assert_eq!(day(&tz, 2025, 3, 3), events(&[("00:00", "Trip"), ("18:00", "Swimming")]));
+        assert_eq!(day(&tz, 2025, 3, 4), events(&[]));
+        assert_eq!(parse_duration("PT1H30M").unwrap(), Duration::minutes(90));
+    }
+
+    #[test]
+    fn skips_lines_that_arent_properties() {
+        let ics = std::fs::read_to_string(FIXTURE).unwrap();
+        let broken = ics.replacen("BEGIN:VEVENT", "BEGIN:VEVENT\r\nnot a property", 1);
+        assert_ne!(ics, broken);
+
+        let tz = chrono_tz::Pacific::Auckland;
+        let start = tz.with_ymd_and_hms(2024, 4, 9, 0, 0, 0).unwrap();
+        let end = start + Duration::days(1);
+        let expected = Calendar::parse(&ics).unwrap().events(start, end);
+        assert_eq!(Calendar::parse(&broken).unwrap().events(start, end), expected);
+        assert_eq!(expected.len(), 3);
+    }
+
+    #[test]
+    fn skips_events_it_cant_understand() {
+        let ics = [
+            "BEGIN:VCALENDAR",
+            "BEGIN:VEVENT",
+            "SUMMARY:Last weekday of the month",
+            "DTSTART:20240401T090000Z",
+            "RRULE:FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:No start",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:Dentist",
+            "DTSTART:20240409T090000Z",
+            "DTEND:20240409T100000Z",
+            "END:VEVENT",
+            "END:VCALENDAR",
+        ]
+        .join("\r\n");
+        let calendar = Calendar::parse(&ics).unwrap();
+
+        let start = Utc.with_ymd_and_hms(2024, 4, 1, 0, 0, 0).unwrap().with_timezone(&chrono_tz::UTC);
+        let summaries: Vec<_> = calendar
+            .events(start, start + Duration::days(30))
+            .into_iter()
+            .map(|event| event.summary)
+            .collect();
+        assert_eq!(summaries, ["Dentist"]);
+    }
+}
@@ -80,6 +80,14 @@ impl<S: Rem<Output = S> + Copy> Rem<S> for RGB<S> {
     }
 }
 
+/// Adds to every channel, e.g. to offset a pixel before quantizing it.
+impl Add<f64> for RGB<f64> {
+    type Output = Self;
+    fn add(self, s: f64) -> Self {
+        self.convert_with(|c| c + s)
+    }
+}
+
 // unary ops
 
 impl<N: Neg<Output = N>> Neg for RGB<N> {
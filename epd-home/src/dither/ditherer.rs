@@ -1,12 +1,30 @@
 //! Logic for dithering a loaded, preprocessed [Img][crate::img::Img].
 //! See [tanner helland's excellent writeup on dithering algorithms](http://www.tannerhelland.com/4660/dithering-eleven-algorithms-source-code/) for details.
-use super::Img;
-use std::ops::{Add, Div, Mul};
+use super::{Img, RGB};
+use std::ops::{Add, Div, Mul, Sub};
 
 /// dither a 2d matrix.
 /// `P`  is the type of pixel; in practice, it is either [f64] or [`RGB<f64>`][RGB]
 pub trait Dither<P> {
     fn dither(&self, img: Img<P>, quantize: impl FnMut(P) -> (P, P)) -> Img<P>;
+
+    /// dither only the pixels where `mask` is set, quantizing the rest to the nearest colour.
+    /// `mask` has one entry per pixel, row by row. Ditherers that carry error between pixels
+    /// should override this so none is carried into or out of the pixels left undithered.
+    fn dither_masked(&self, img: Img<P>, mut quantize: impl FnMut(P) -> (P, P), mask: &[bool]) -> Img<P>
+    where
+        P: Clone,
+    {
+        let width = img.width();
+        let nearest: Vec<P> = img.iter().cloned().map(|p| quantize(p).0).collect();
+        let dithered = self.dither(img, &mut quantize);
+        let pixels = dithered
+            .into_iter()
+            .zip(nearest)
+            .zip(mask)
+            .map(|((dithered, nearest), diffuse)| if *diffuse { dithered } else { nearest });
+        Img::new(pixels, width).unwrap()
+    }
 }
 /// A type of Dither. See the documentation for the constants (i.e, [ATKINSON]) for the dither matrices themselves.
 /// A ditherer carries error from quantiation to nearby pixels after dividing by `div` and multiplying by the given scalar in offset; "spreading" the error,
@@ -29,6 +47,10 @@ pub struct Ditherer<'a> {
     /// offsets represents a triplet (dx, dy, mul)
     offsets: &'a [(isize, isize, f64)],
     name: Option<&'a str>,
+    /// scan every other row right-to-left, mirroring the offsets
+    serpentine: bool,
+    /// carry error in linear light rather than sRGB
+    linear: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -41,31 +63,118 @@ impl<'a> Ditherer<'a> {
             div,
             offsets,
             name: None,
+            serpentine: false,
+            linear: false,
         }
     }
+
+    /// scan alternate rows in opposite directions (boustrophedon), which avoids the diagonal
+    /// "worms" that build up when error is always pushed the same way.
+    pub fn with_serpentine(self, serpentine: bool) -> Self {
+        Ditherer { serpentine, ..self }
+    }
+
+    /// diffuse error in linear light, so that dithered areas keep the brightness of the
+    /// original instead of coming out too dark in the midtones.
+    pub fn with_linear_light(self, linear: bool) -> Self {
+        Ditherer { linear, ..self }
+    }
+}
+
+/// conversion between sRGB-encoded and linear light values, both from 0 to 255.
+pub trait LinearLight {
+    fn to_linear(self) -> Self;
+    fn to_srgb(self) -> Self;
+}
+
+impl LinearLight for f64 {
+    fn to_linear(self) -> Self {
+        let c = (self / 255.).clamp(0., 1.);
+        255. * if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    }
+
+    fn to_srgb(self) -> Self {
+        let c = (self / 255.).clamp(0., 1.);
+        255. * if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1. / 2.4) - 0.055 }
+    }
+}
+
+impl LinearLight for RGB<f64> {
+    fn to_linear(self) -> Self {
+        self.convert_with(f64::to_linear)
+    }
+
+    fn to_srgb(self) -> Self {
+        self.convert_with(f64::to_srgb)
+    }
 }
 
 impl<'a, P> Dither<P> for Ditherer<'a>
 where
-    P: Add<Output = P> + Clone + Default,           // vec addition
+    P: Add<Output = P> + Sub<Output = P> + Clone,   // vec addition
     P: Mul<f64, Output = P> + Div<f64, Output = P>, // scalar multiplication
+    P: LinearLight,
 {
     /// dither an image using the specified offsets and divisor.
     /// `P` is the type of pixel; in practice, it is either [f64] or [RGB<f64]
-    fn dither(&self, mut img: Img<P>, mut quantize: impl FnMut(P) -> (P, P)) -> super::Img<P> {
-        let width = img.width() as isize;
-        let mut spillover = vec![P::default(); img.len()];
-        for (i, p) in img.iter_mut().enumerate() {
-            let (quantized, spill) = quantize(p.clone() + spillover[i].clone());
-            *p = quantized;
-
-            // add spillover matrices
-            for (dx, dy, mul) in self.offsets.iter().cloned() {
-                let j = i as isize + (dy * width) + dx;
-
-                if let Some(stored_spill) = spillover.get_mut(j as usize) {
-                    // this cast is OK, since if we go past the edges, we get zero
-                    *stored_spill = stored_spill.clone() + (spill.clone() * mul) / self.div;
+    fn dither(&self, img: Img<P>, quantize: impl FnMut(P) -> (P, P)) -> super::Img<P> {
+        self.diffuse(img, quantize, None)
+    }
+
+    /// pixels outside the mask are quantized as they are, and error is neither carried to nor
+    /// from them, so it can't bleed between e.g. text and a photo next to it.
+    fn dither_masked(&self, img: Img<P>, quantize: impl FnMut(P) -> (P, P), mask: &[bool]) -> Img<P> {
+        self.diffuse(img, quantize, Some(mask))
+    }
+}
+
+impl<'a> Ditherer<'a> {
+    /// error diffusion over the pixels where `mask` is set, or all of them without one.
+    fn diffuse<P>(&self, mut img: Img<P>, mut quantize: impl FnMut(P) -> (P, P), mask: Option<&[bool]>) -> Img<P>
+    where
+        P: Add<Output = P> + Sub<Output = P> + Clone,
+        P: Mul<f64, Output = P> + Div<f64, Output = P>,
+        P: LinearLight,
+    {
+        let (width, height) = (img.width() as isize, img.height() as isize);
+        let diffused = |i: isize| mask.map_or(true, |mask| mask[i as usize]);
+
+        // the pixels plus the error carried to them so far
+        let mut values: Vec<P> = match self.linear {
+            true => img.iter().cloned().map(P::to_linear).collect(),
+            false => img.iter().cloned().collect(),
+        };
+
+        for y in 0..height {
+            let reverse = self.serpentine && y % 2 == 1;
+            for step in 0..width {
+                let x = if reverse { width - 1 - step } else { step };
+                if !diffused(y * width + x) {
+                    let (quantized, _) = quantize(img[(x as u32, y as u32)].clone());
+                    img[(x as u32, y as u32)] = quantized;
+                    continue;
+                }
+                let value = values[(y * width + x) as usize].clone();
+
+                // the quantizer works in sRGB, so the error is measured against what it picked
+                let (quantized, spill) = match self.linear {
+                    true => {
+                        let (quantized, _) = quantize(value.clone().to_srgb());
+                        let spill = value - quantized.clone().to_linear();
+                        (quantized, spill)
+                    }
+                    false => quantize(value),
+                };
+                img[(x as u32, y as u32)] = quantized;
+
+                // add spillover matrices, dropping anything that falls off the image or the mask
+                for (dx, dy, mul) in self.offsets.iter().cloned() {
+                    let (tx, ty) = (if reverse { x - dx } else { x + dx }, y + dy);
+                    if tx < 0 || tx >= width || ty >= height || !diffused(ty * width + tx) {
+                        continue;
+                    }
+                    let stored = &mut values[(ty * width + tx) as usize];
+                    *stored = stored.clone() + (spill.clone() * mul) / self.div;
                 }
             }
         }
@@ -94,6 +203,8 @@ impl std::str::FromStr for Ditherer<'static> {
 /// - `.  1  .  .`
 pub const ATKINSON: Ditherer = Ditherer {
     name: Some("atkinson"),
+    serpentine: false,
+    linear: false,
     div: 8.,
     offsets: &[
         // (dx, dy, mul)
@@ -113,6 +224,8 @@ pub const ATKINSON: Ditherer = Ditherer {
 /// - ` 2  4  8  4  2`
 pub const BURKES: Ditherer = Ditherer {
     name: Some("burkes"),
+    serpentine: false,
+    linear: false,
     div: 32.,
     offsets: &[
         // (dx, dy, mul)
@@ -133,6 +246,8 @@ pub const BURKES: Ditherer = Ditherer {
 /// - ` 7 5  1`
 pub const FLOYD_STEINBERG: Ditherer = Ditherer {
     name: Some("floyd"),
+    serpentine: false,
+    linear: false,
     div: 16.,
     offsets: &[(1, 0, 7.), (-1, 1, 3.), (0, 1, 5.), (1, 1, 1.)],
 };
@@ -144,6 +259,8 @@ pub const FLOYD_STEINBERG: Ditherer = Ditherer {
 /// - ` 1  2  4  2  1`
 pub const STUCKI: Ditherer = Ditherer {
     name: Some("stucki"),
+    serpentine: false,
+    linear: false,
     div: 42.,
     offsets: &[
         // (dx, dy, mul)
@@ -171,6 +288,8 @@ pub const STUCKI: Ditherer = Ditherer {
 /// - `1  3  5  3  1`  
 pub const JARVIS_JUDICE_NINKE: Ditherer = Ditherer {
     name: Some("jarvis"),
+    serpentine: false,
+    linear: false,
     div: 48.0,
     offsets: &[
         // (dx, dy, mul)
@@ -197,6 +316,8 @@ pub const JARVIS_JUDICE_NINKE: Ditherer = Ditherer {
 /// - `.  2  3  2  .`
 pub const SIERRA_3: Ditherer = Ditherer {
     name: Some("sierra3"),
+    serpentine: false,
+    linear: false,
     div: 32.,
     offsets: &[
         // (dx, dy, mul)
@@ -246,7 +367,8 @@ impl<'a> Eq for Ditherer<'a> {}
 
 impl<'a> PartialEq for Ditherer<'a> {
     fn eq(&self, other: &Self) -> bool {
-        (self.div, self.offsets) == (other.div, other.offsets)
+        (self.div, self.offsets, self.serpentine, self.linear)
+            == (other.div, other.offsets, other.serpentine, other.linear)
     }
 }
 
@@ -255,3 +377,36 @@ impl<'a> Default for Ditherer<'a> {
         FLOYD_STEINBERG
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_error_past_the_edges() {
+        // 2x2 with a grey pixel on the right edge of the first row
+        let img = Img::new(vec![0., 100., 0., 0.], 2).unwrap();
+        let mut inputs = Vec::new();
+        FLOYD_STEINBERG.with_serpentine(true).dither(img, |p: f64| {
+            inputs.push(p);
+            (0., p)
+        });
+
+        // The second row is scanned right to left, and its left pixel only gets the error
+        // diffused down and to the left, not what would wrap around from the right
+        assert_eq!(inputs, [0., 100., 100. * 5. / 16., 100. * 3. / 16. + 100. * 5. / 16. * 7. / 16.]);
+    }
+
+    #[test]
+    fn keeps_brightness_in_linear_light() {
+        let grey = Img::new(vec![128.; 64 * 64], 64).unwrap();
+        let quantize = crate::dither::create_quantize_n_bits_func(1).unwrap();
+        let white = |img: Img<f64>| img.iter().filter(|p| **p == 255.).count() as f64 / img.len() as f64;
+
+        // sRGB 128 is about 22% as bright as white
+        let linear = white(STUCKI.with_linear_light(true).dither(grey.clone(), &quantize));
+        assert!((0.19..0.25).contains(&linear), "{}", linear);
+        let srgb = white(STUCKI.dither(grey, &quantize));
+        assert!((0.47..0.53).contains(&srgb), "{}", srgb);
+    }
+}
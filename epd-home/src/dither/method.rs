@@ -0,0 +1,91 @@
+//! Choosing between error diffusion and ordered dithering by name.
+use super::{
+    ditherer::{Dither, Ditherer, ErrorUnknownDitherer, LinearLight},
+    ordered::{self, Ordered},
+    Img,
+};
+use std::ops::{Add, Div, Mul, Sub};
+
+/// Either kind of dithering.
+#[derive(Clone, Debug)]
+pub enum Method<'a> {
+    /// Error diffusion, e.g. [STUCKI][super::ditherer::STUCKI].
+    Diffusion(Ditherer<'a>),
+    /// A threshold map, e.g. [BAYER_4][ordered::BAYER_4], which is stable between frames.
+    Ordered(Ordered<'a>),
+}
+
+impl<'a> Method<'a> {
+    /// See [Ordered::with_spread]. Error diffusion doesn't need it.
+    pub fn with_spread(self, spread: f64) -> Self {
+        match self {
+            Method::Ordered(ordered) => Method::Ordered(ordered.with_spread(spread)),
+            diffusion => diffusion,
+        }
+    }
+
+    /// See [Ditherer::with_serpentine]. Ordered dithering has no scan order.
+    pub fn with_serpentine(self, serpentine: bool) -> Self {
+        match self {
+            Method::Diffusion(ditherer) => Method::Diffusion(ditherer.with_serpentine(serpentine)),
+            ordered => ordered,
+        }
+    }
+
+    /// See [Ditherer::with_linear_light]. Ordered dithering thresholds in sRGB regardless.
+    pub fn with_linear_light(self, linear: bool) -> Self {
+        match self {
+            Method::Diffusion(ditherer) => Method::Diffusion(ditherer.with_linear_light(linear)),
+            ordered => ordered,
+        }
+    }
+}
+
+impl<'a, P> Dither<P> for Method<'a>
+where
+    P: Add<Output = P> + Add<f64, Output = P> + Sub<Output = P> + Clone,
+    P: Mul<f64, Output = P> + Div<f64, Output = P>,
+    P: LinearLight,
+{
+    fn dither(&self, img: Img<P>, quantize: impl FnMut(P) -> (P, P)) -> Img<P> {
+        match self {
+            Method::Diffusion(ditherer) => ditherer.dither(img, quantize),
+            Method::Ordered(ordered) => ordered.dither(img, quantize),
+        }
+    }
+
+    fn dither_masked(&self, img: Img<P>, quantize: impl FnMut(P) -> (P, P), mask: &[bool]) -> Img<P> {
+        match self {
+            Method::Diffusion(ditherer) => ditherer.dither_masked(img, quantize, mask),
+            Method::Ordered(ordered) => ordered.dither_masked(img, quantize, mask),
+        }
+    }
+}
+
+impl std::str::FromStr for Method<'static> {
+    type Err = ErrorUnknownDitherer;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_ref() {
+            "bayer2" => Method::Ordered(ordered::BAYER_2),
+            "bayer4" => Method::Ordered(ordered::BAYER_4),
+            "bayer" | "bayer8" => Method::Ordered(ordered::BAYER_8),
+            "blue" | "bluenoise" | "blue-noise" | "blue noise" => Method::Ordered(ordered::blue_noise()),
+            _ => Method::Diffusion(s.parse()?),
+        })
+    }
+}
+
+impl<'a> std::fmt::Display for Method<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Method::Diffusion(ditherer) => ditherer.fmt(f),
+            Method::Ordered(ordered) => ordered.fmt(f),
+        }
+    }
+}
+
+impl<'a> From<Ditherer<'a>> for Method<'a> {
+    fn from(ditherer: Ditherer<'a>) -> Self {
+        Method::Diffusion(ditherer)
+    }
+}
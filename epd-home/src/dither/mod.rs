@@ -9,6 +9,9 @@ pub mod color;
 pub mod ditherer;
 mod error;
 mod img;
+pub mod method;
+pub mod ordered;
+pub mod selective;
 pub mod prelude;
 pub use self::error::Error;
 pub use self::error::Result;
@@ -30,7 +33,8 @@ pub fn create_quantize_n_bits_func(n: u8) -> Result<impl Fn(f64) -> (f64, f64)>
         Err(Error::BadBitDepth(n))
     } else {
         Ok(move |x: f64| {
-            let step_size = 255. / f64::from(n);
+            // 2^n levels, so n - 1 steps between black and white
+            let step_size = 255. / f64::from((1u8 << n) - 1);
 
             let floor = f64::floor(x / step_size) * step_size;
             let floor_rem = x - floor;
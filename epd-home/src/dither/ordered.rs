@@ -0,0 +1,229 @@
+//! Ordered dithering with a tiled threshold map, see [Ordered].
+use super::{ditherer::Dither, Img};
+use std::{ops::Add, sync::OnceLock};
+
+/// Ordered dithering: each pixel is offset by its entry in a threshold map tiled across the
+/// image, then quantized on its own. Unlike error diffusion, a pixel only depends on its own
+/// value and position, so unchanged parts of an image dither identically from one frame to the
+/// next.
+///
+/// `ranks` is a `size` x `size` map, row by row, holding each of `0..size * size` once.
+#[derive(Clone, Debug)]
+pub struct Ordered<'a> {
+    size: usize,
+    ranks: &'a [u16],
+    /// How far apart the levels being quantized to are
+    spread: f64,
+    name: &'a str,
+}
+
+impl<'a> Ordered<'a> {
+    /// Offsets range over `spread`, which should be about the distance between neighbouring
+    /// levels the image is quantized to, e.g. `255.` for black and white.
+    pub fn with_spread(self, spread: f64) -> Self {
+        Ordered { spread, ..self }
+    }
+
+    /// The offset at `(x, y)`, from `-spread / 2` to `spread / 2`.
+    fn offset(&self, x: usize, y: usize) -> f64 {
+        let rank = self.ranks[(y % self.size) * self.size + x % self.size];
+        ((f64::from(rank) + 0.5) / self.ranks.len() as f64 - 0.5) * self.spread
+    }
+}
+
+impl<'a, P> Dither<P> for Ordered<'a>
+where
+    P: Add<f64, Output = P> + Clone,
+{
+    fn dither(&self, mut img: Img<P>, mut quantize: impl FnMut(P) -> (P, P)) -> Img<P> {
+        let width = img.width() as usize;
+        for (i, p) in img.iter_mut().enumerate() {
+            let (quantized, _) = quantize(p.clone() + self.offset(i % width, i / width));
+            *p = quantized;
+        }
+        img
+    }
+}
+
+impl<'a> std::fmt::Display for Ordered<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+/// Bayer 2x2.
+/// - `0 2`
+/// - `3 1`
+pub const BAYER_2: Ordered = Ordered {
+    size: 2,
+    ranks: &[0, 2, 3, 1],
+    spread: 255.,
+    name: "bayer2",
+};
+
+/// Bayer 4x4.
+pub const BAYER_4: Ordered = Ordered {
+    size: 4,
+    ranks: &[
+        0, 8, 2, 10, //
+        12, 4, 14, 6, //
+        3, 11, 1, 9, //
+        15, 7, 13, 5,
+    ],
+    spread: 255.,
+    name: "bayer4",
+};
+
+/// Bayer 8x8.
+pub const BAYER_8: Ordered = Ordered {
+    size: 8,
+    ranks: &[
+        0, 32, 8, 40, 2, 34, 10, 42, //
+        48, 16, 56, 24, 50, 18, 58, 26, //
+        12, 44, 4, 36, 14, 46, 6, 38, //
+        60, 28, 52, 20, 62, 30, 54, 22, //
+        3, 35, 11, 43, 1, 33, 9, 41, //
+        51, 19, 59, 27, 49, 17, 57, 25, //
+        15, 47, 7, 39, 13, 45, 5, 37, //
+        63, 31, 55, 23, 61, 29, 53, 21,
+    ],
+    spread: 255.,
+    name: "bayer8",
+};
+
+/// Side of the blue noise map.
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// A 64x64 blue noise map, which avoids the visible cross-hatching of Bayer matrices. It's
+/// generated on first use, with a fixed seed so it's the same every time.
+pub fn blue_noise() -> Ordered<'static> {
+    static RANKS: OnceLock<Vec<u16>> = OnceLock::new();
+    Ordered {
+        size: BLUE_NOISE_SIZE,
+        ranks: RANKS.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5)),
+        spread: 255.,
+        name: "blue-noise",
+    }
+}
+
+/// Ulichney's void-and-cluster method: repeatedly swap the most tightly clustered point of a
+/// random pattern into the largest void until it's evenly spread, then rank points by removing
+/// clusters and filling voids from there.
+fn void_and_cluster(size: usize, sigma: f64) -> Vec<u16> {
+    let n = size * size;
+
+    // Gaussian weight by wrapped distance, so the map tiles seamlessly
+    let wrap = |d: usize| d.min(size - d) as f64;
+    let kernel: Vec<f64> = (0..n)
+        .map(|i| {
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2. * sigma * sigma)).exp()
+        })
+        .collect();
+
+    // How clustered each pixel's surroundings are, from the points set in `pattern`
+    let mut energy = vec![0.; n];
+    let mut pattern = vec![false; n];
+    let toggle = |pattern: &mut [bool], energy: &mut [f64], i: usize| {
+        pattern[i] = !pattern[i];
+        let sign = if pattern[i] { 1. } else { -1. };
+        let (x0, y0) = (i % size, i / size);
+        for (j, e) in energy.iter_mut().enumerate() {
+            let (dx, dy) = ((j % size + size - x0) % size, (j / size + size - y0) % size);
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    let tightest_cluster = |pattern: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |pattern: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // A tenth of the pixels set at random, from a fixed xorshift seed
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    let mut set = 0;
+    while set < n / 10 {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        let i = (seed % n as u64) as usize;
+        if !pattern[i] {
+            toggle(&mut pattern, &mut energy, i);
+            set += 1;
+        }
+    }
+
+    loop {
+        let cluster = tightest_cluster(&pattern, &energy);
+        toggle(&mut pattern, &mut energy, cluster);
+        let void = largest_void(&pattern, &energy);
+        toggle(&mut pattern, &mut energy, void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; n];
+    let (initial_pattern, initial_energy) = (pattern.clone(), energy.clone());
+    for rank in (0..set).rev() {
+        let cluster = tightest_cluster(&pattern, &energy);
+        toggle(&mut pattern, &mut energy, cluster);
+        ranks[cluster] = rank as u16;
+    }
+
+    // The emptiest spot among unset pixels is also the tightest cluster of unset pixels, so
+    // this carries on all the way up
+    let (mut pattern, mut energy) = (initial_pattern, initial_energy);
+    for rank in set..n {
+        let void = largest_void(&pattern, &energy);
+        toggle(&mut pattern, &mut energy, void);
+        ranks[void] = rank as u16;
+    }
+
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_stay_local() {
+        let quantize = crate::dither::create_quantize_n_bits_func(1).unwrap();
+        let prev = Img::new(vec![128.; 64], 8).unwrap();
+        let mut next = prev.clone();
+        next[(5, 5)] = 0.;
+
+        let prev = BAYER_4.dither(prev, &quantize);
+        let next = BAYER_4.dither(next, &quantize);
+        let changed = prev.iter().zip(next.iter()).filter(|(a, b)| a != b).count();
+        assert!(changed <= 1);
+        // A 50% grey is half black
+        assert_eq!(prev.iter().filter(|p| **p == 0.).count(), 32);
+    }
+
+    #[test]
+    fn blue_noise_ranks_each_pixel_once() {
+        let noise = blue_noise();
+        let mut ranks = noise.ranks.to_vec();
+        ranks.sort();
+        assert!(ranks.iter().copied().eq(0..(BLUE_NOISE_SIZE * BLUE_NOISE_SIZE) as u16));
+
+        // Every 8x8 block of a 50% threshold is roughly half set, unlike white noise
+        let half = ranks.len() as u16 / 2;
+        for block in 0..BLUE_NOISE_SIZE * BLUE_NOISE_SIZE / 64 {
+            let (bx, by) = (block % 8 * 8, block / 8 * 8);
+            let set = (0..64)
+                .filter(|i| noise.ranks[(by + i / 8) * BLUE_NOISE_SIZE + bx + i % 8] < half)
+                .count();
+            assert!((24..=40).contains(&set), "{} of 64 set", set);
+        }
+    }
+}
@@ -6,4 +6,5 @@ pub use self::{
     ditherer::{Dither, Ditherer},
     error::{Error, Result},
     img::Img,
+    method::Method,
 };
@@ -0,0 +1,59 @@
+//! Dithering only part of an image, see [Selective].
+use super::{ditherer::Dither, Img};
+
+/// Dithers the pixels where `mask` is set, and quantizes the rest to the nearest colour, so that
+/// e.g. text keeps crisp edges next to a dithered photo. See [Dither::dither_masked].
+///
+/// `mask` has one entry per pixel, row by row.
+#[derive(Clone, Debug)]
+pub struct Selective<'a, D> {
+    inner: D,
+    mask: &'a [bool],
+}
+
+impl<'a, D> Selective<'a, D> {
+    pub fn new(inner: D, mask: &'a [bool]) -> Self {
+        Selective { inner, mask }
+    }
+}
+
+impl<'a, P, D> Dither<P> for Selective<'a, D>
+where
+    P: Clone,
+    D: Dither<P>,
+{
+    fn dither(&self, img: Img<P>, mut quantize: impl FnMut(P) -> (P, P)) -> Img<P> {
+        let width = img.width();
+        if !self.mask.iter().any(|diffuse| *diffuse) {
+            return Img::new(img.into_iter().map(|p| quantize(p).0), width).unwrap();
+        }
+
+        self.inner.dither_masked(img, quantize, self.mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dither::{create_quantize_n_bits_func, ditherer::FLOYD_STEINBERG};
+
+    #[test]
+    fn keeps_error_on_its_own_side_of_the_mask() {
+        // A flat grey to threshold on the left, and a gradient to dither on the right
+        let (width, height) = (16, 8);
+        let gradient = |x: u32| 255. * f64::from(x) / 7.;
+        let pixels = (0..height).flat_map(|_| (0..width).map(|x| if x < 8 { 100. } else { gradient(x - 8) }));
+        let mask = (0..height).flat_map(|_| (0..width).map(|x| x >= 8)).collect::<Vec<_>>();
+        let quantize = create_quantize_n_bits_func(1).unwrap();
+
+        let img = Selective::new(FLOYD_STEINBERG, &mask).dither(Img::new(pixels, width).unwrap(), &quantize);
+
+        // The gradient comes out as if it were dithered on its own
+        let alone = (0..height).flat_map(|_| (0..8).map(gradient));
+        let alone = FLOYD_STEINBERG.dither(Img::new(alone, 8).unwrap(), &quantize);
+        for y in 0..height {
+            assert!((0..8).all(|x| img[(x, y)] == 0.));
+            assert!((0..8).all(|x| img[(x + 8, y)] == alone[(x, y)]));
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Rendered frames, stored packed at the panel's colour depth.
+
+use std::{io::Cursor, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    ink::{Colors, Ink},
+    raw::{self, RawOptions},
+    screen::{Error, Result},
+};
+
+/// Rotation clockwise, in quarter turns. Parsed from degrees: 0, 90, 180 or 270.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(try_from = "u16")]
+pub enum Rotation {
+    #[default]
+    None,
+    Quarter,
+    Half,
+    ThreeQuarter,
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = Error;
+
+    fn try_from(degrees: u16) -> Result<Self> {
+        match degrees {
+            0 => Ok(Rotation::None),
+            90 => Ok(Rotation::Quarter),
+            180 => Ok(Rotation::Half),
+            270 => Ok(Rotation::ThreeQuarter),
+            _ => Err(Error::InvalidRotation(degrees.to_string())),
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<u16>()
+            .map_err(|_| Error::InvalidRotation(s.to_string()))?
+            .try_into()
+    }
+}
+
+/// A frame of pixels for a panel with the given [`Colors`].
+///
+/// Each pixel is stored as an index into the panel's inks, at 1 bit per pixel for black and
+/// white, 2 for three colours and up to 4 otherwise, with each row starting on a new byte.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Frame {
+    width: u32,
+    height: u32,
+    colors: Colors,
+    inks: Vec<Ink>,
+    bits: usize,
+    stride: usize,
+    data: Vec<u8>,
+}
+
+impl Frame {
+    /// A white frame.
+    pub fn new(width: u32, height: u32, colors: Colors) -> Self {
+        let inks = colors.inks();
+        let bits = match colors {
+            Colors::BlackWhite => 1,
+            Colors::ThreeColor(_) => 2,
+            Colors::Acep | Colors::Spectra6 => 4,
+            Colors::Grayscale(bits) => bits as usize,
+        };
+        let stride = (width as usize * bits).div_ceil(8);
+
+        let mut frame = Self {
+            width,
+            height,
+            colors,
+            inks,
+            bits,
+            stride,
+            data: vec![0; stride * height as usize],
+        };
+        let white = frame.slot(Ink::White);
+        if white != 0 {
+            let per_byte = 8 / bits;
+            let byte = (0..per_byte).fold(0u8, |byte, i| byte | (white << (8 - bits * (i + 1))));
+            frame.data.fill(byte);
+        }
+        frame
+    }
+
+    /// A frame with each pixel from `ink(x, y)`.
+    pub fn from_fn(width: u32, height: u32, colors: Colors, mut ink: impl FnMut(u32, u32) -> Ink) -> Self {
+        let mut frame = Self::new(width, height, colors);
+        for y in 0..height {
+            for x in 0..width {
+                frame.set(x, y, ink(x, y));
+            }
+        }
+        frame
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn colors(&self) -> Colors {
+        self.colors
+    }
+
+    /// The pixel at `(x, y)`, or `None` if it's outside the frame.
+    pub fn get(&self, x: u32, y: u32) -> Option<Ink> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let (i, shift) = self.position(x, y);
+        let mask = (1u8 << self.bits) - 1;
+        let slot = (self.data[i] >> shift) & mask;
+        self.inks.get(slot as usize).copied()
+    }
+
+    /// Sets the pixel at `(x, y)` to the nearest ink the panel has, e.g. red is black on a black
+    /// and white panel and yellow is white. Outside the frame, does nothing.
+    pub fn set(&mut self, x: u32, y: u32, ink: Ink) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let (i, shift) = self.position(x, y);
+        let mask = ((1u8 << self.bits) - 1) << shift;
+        self.data[i] = (self.data[i] & !mask) | (self.slot(ink) << shift);
+    }
+
+    /// Pixels row by row.
+    pub fn pixels(&self) -> impl Iterator<Item = Ink> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| self.get(x, y).unwrap()))
+    }
+
+    /// The given region of the frame. Anything outside the frame is white.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Frame {
+        Frame::from_fn(width, height, self.colors, |dx, dy| {
+            self.get(x + dx, y + dy).unwrap_or(Ink::White)
+        })
+    }
+
+    /// The frame rotated clockwise, e.g. to draw a landscape layout on a portrait panel.
+    pub fn rotate(&self, rotation: Rotation) -> Frame {
+        let (w, h) = (self.width, self.height);
+        match rotation {
+            Rotation::None => self.clone(),
+            Rotation::Quarter => Frame::from_fn(h, w, self.colors, |x, y| self.get(y, h - 1 - x).unwrap()),
+            Rotation::Half => Frame::from_fn(w, h, self.colors, |x, y| self.get(w - 1 - x, h - 1 - y).unwrap()),
+            Rotation::ThreeQuarter => Frame::from_fn(h, w, self.colors, |x, y| self.get(w - 1 - y, x).unwrap()),
+        }
+    }
+
+    /// Packed for the panel's controller, see [`raw::pack_for_panel`].
+    pub fn to_raw(&self, options: &RawOptions) -> Vec<u8> {
+        raw::pack_for_panel(self, options)
+    }
+
+    /// A 1 bit BMP, for black and white frames.
+    pub fn to_bmp(&self) -> Result<Vec<u8>> {
+        self.require_black_white("BMP")?;
+
+        let mut buff = Cursor::new(Vec::new());
+        bmp_monochrome::Bmp::new(self.black_rows())?.write(&mut buff)?;
+        Ok(buff.into_inner())
+    }
+
+    /// A binary PBM, for black and white frames.
+    pub fn to_pbm(&self) -> Result<Vec<u8>> {
+        self.require_black_white("PBM")?;
+
+        let mut data = format!("P4\n{} {}\n", self.width, self.height).into_bytes();
+        // PBM is always most significant bit first with 1 for black
+        let pbm_options = RawOptions { invert: true, ..Default::default() };
+        data.extend(raw::pack(self, &pbm_options));
+        Ok(data)
+    }
+
+    /// A binary PGM with 8 bit levels.
+    pub fn to_pgm(&self) -> Vec<u8> {
+        let mut data = format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+        data.extend(self.to_luma8());
+        data
+    }
+
+    /// 8 bit grey levels, row by row, e.g. for a greyscale PNG.
+    pub fn to_luma8(&self) -> Vec<u8> {
+        self.pixels().map(Ink::luma).collect()
+    }
+
+    /// 8 bit RGB pixels, row by row, for previewing a frame.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.pixels()
+            .flat_map(|px| {
+                let (r, g, b) = px.rgb();
+                [r, g, b]
+            })
+            .collect()
+    }
+
+    fn black_rows(&self) -> Vec<Vec<bool>> {
+        (0..self.height)
+            .map(|y| (0..self.width).map(|x| self.get(x, y) == Some(Ink::Black)).collect())
+            .collect()
+    }
+
+    fn require_black_white(&self, format: &'static str) -> Result<()> {
+        match self.colors {
+            Colors::BlackWhite => Ok(()),
+            _ => Err(Error::BlackWhiteOnly(format)),
+        }
+    }
+
+    /// Byte index and shift of a pixel, with the first pixel in the highest bits.
+    fn position(&self, x: u32, y: u32) -> (usize, usize) {
+        let bit = x as usize * self.bits;
+        let i = y as usize * self.stride + bit / 8;
+        (i, 8 - self.bits - bit % 8)
+    }
+
+    fn slot(&self, ink: Ink) -> u8 {
+        if let Colors::Grayscale(_) = self.colors {
+            return self.colors.index(ink);
+        }
+        if let Some(slot) = self.inks.iter().position(|i| *i == ink) {
+            return slot as u8;
+        }
+        let (r, g, b) = ink.rgb();
+        let distance = |other: &Ink| {
+            let (r2, g2, b2) = other.rgb();
+            [(r, r2), (g, g2), (b, b2)]
+                .into_iter()
+                .map(|(a, b)| (i32::from(a) - i32::from(b)).pow(2))
+                .sum::<i32>()
+        };
+        let nearest = (0..self.inks.len()).min_by_key(|&slot| distance(&self.inks[slot]));
+        nearest.unwrap_or(1) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ink::Accent;
+
+    #[test]
+    fn stores_pixels_packed() {
+        let mut frame = Frame::new(10, 3, Colors::ThreeColor(Accent::Red));
+        frame.set(9, 2, Ink::Red);
+        frame.set(0, 0, Ink::Black);
+        // Not on the panel, so the nearest is black
+        frame.set(1, 0, Ink::Blue);
+
+        assert_eq!(frame.get(9, 2), Some(Ink::Red));
+        assert_eq!(frame.get(0, 0), Some(Ink::Black));
+        assert_eq!(frame.get(1, 0), Some(Ink::Black));
+        assert_eq!(frame.get(10, 0), None);
+        assert_eq!(frame.data.len(), 3 * 3);
+
+        let mut frame = Frame::new(2, 1, Colors::BlackWhite);
+        frame.set(0, 0, Ink::Red);
+        frame.set(1, 0, Ink::Yellow);
+        assert_eq!(frame.get(0, 0), Some(Ink::Black));
+        assert_eq!(frame.get(1, 0), Some(Ink::White));
+    }
+
+    #[test]
+    fn crops_and_rotates() {
+        // 3 x 2, black in the top left
+        let frame = Frame::from_fn(3, 2, Colors::BlackWhite, |x, y| {
+            if (x, y) == (0, 0) { Ink::Black } else { Ink::White }
+        });
+
+        let quarter = frame.rotate(Rotation::Quarter);
+        assert_eq!((quarter.width(), quarter.height()), (2, 3));
+        assert_eq!(quarter.get(1, 0), Some(Ink::Black));
+        assert_eq!(frame.rotate(Rotation::Half).get(2, 1), Some(Ink::Black));
+        assert_eq!(frame.rotate(Rotation::ThreeQuarter).get(0, 2), Some(Ink::Black));
+
+        let cropped = frame.crop(0, 0, 4, 1);
+        assert_eq!(cropped.pixels().collect::<Vec<_>>(), [Ink::Black, Ink::White, Ink::White, Ink::White]);
+    }
+}
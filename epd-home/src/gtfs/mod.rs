@@ -0,0 +1,519 @@
+//! Arrivals from a static [GTFS](https://gtfs.org/schedule/) timetable combined with a
+//! [GTFS-Realtime](https://gtfs.org/realtime/) TripUpdates feed, which most transit agencies publish.
+//!
+//! The timetable is used to find the stops, routes and headsigns, and the scheduled times that
+//! delay-only predictions are relative to. Only trips that appear in the TripUpdates feed are shown,
+//! because working out which scheduled trips run today would also need `calendar.txt`.
+
+mod realtime;
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::Read,
+    path::Path,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use itertools::Itertools;
+use prost::Message;
+use serde::{de::DeserializeOwned, Deserialize};
+use zip::ZipArchive;
+
+use self::realtime::{
+    FeedMessage, StopScheduleRelationship, StopTimeUpdate, TripScheduleRelationship, TripUpdate,
+};
+use crate::{
+    screen::{Error, Result},
+    transport::{ArrivalsSource, RouteArrivals},
+};
+
+#[derive(Deserialize)]
+struct AgencyRecord {
+    agency_timezone: String,
+}
+
+#[derive(Deserialize)]
+struct StopRecord {
+    stop_id: String,
+    #[serde(default)]
+    stop_code: Option<String>,
+    #[serde(default)]
+    parent_station: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RouteRecord {
+    route_id: String,
+    #[serde(default)]
+    route_short_name: Option<String>,
+    #[serde(default)]
+    route_long_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TripRecord {
+    route_id: String,
+    trip_id: String,
+    #[serde(default)]
+    trip_headsign: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StopTimeRecord {
+    trip_id: String,
+    #[serde(default)]
+    arrival_time: Option<String>,
+    #[serde(default)]
+    departure_time: Option<String>,
+    stop_id: String,
+    stop_sequence: u32,
+    #[serde(default)]
+    stop_headsign: Option<String>,
+}
+
+struct Trip {
+    route: String,
+    headsign: String,
+}
+
+struct StopTime {
+    stop_sequence: u32,
+    stop_id: String,
+    /// Seconds since the start of the service day, which may be more than 24 hours.
+    arrival: Option<u32>,
+    headsign: Option<String>,
+}
+
+/// The parts of a static GTFS feed needed to interpret TripUpdates.
+struct Schedule {
+    timezone: Tz,
+    /// Stop IDs for each stop code, including the platforms of stations.
+    stop_ids: HashMap<String, HashSet<String>>,
+    trips: HashMap<String, Trip>,
+    /// Ordered by stop sequence.
+    stop_times: HashMap<String, Vec<StopTime>>,
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.trim().is_empty())
+}
+
+/// Parses a GTFS time such as `25:10:00` into seconds since the start of the service day.
+fn parse_gtfs_time(time: &str) -> Option<u32> {
+    let mut parts = time.trim().split(':').map(|part| part.parse::<u32>().ok());
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Some(h)), Some(Some(m)), Some(Some(s)), None) => Some(h * 3600 + m * 60 + s),
+        _ => None,
+    }
+}
+
+fn read_records<T: DeserializeOwned, R: Read + std::io::Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Vec<T>> {
+    let file = archive.by_name(name)?;
+    let records = csv::Reader::from_reader(file)
+        .into_deserialize()
+        .collect::<Result<Vec<T>, csv::Error>>()?;
+    Ok(records)
+}
+
+impl Schedule {
+    fn load(path: &Path) -> Result<Self> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+
+        let timezone = read_records::<AgencyRecord, _>(&mut archive, "agency.txt")?
+            .first()
+            .ok_or_else(|| Error::MissingData("agency".into()))?
+            .agency_timezone
+            .parse()
+            .map_err(|_| Error::InvalidTimezone)?;
+
+        let stops: Vec<StopRecord> = read_records(&mut archive, "stops.txt")?;
+        let mut stop_ids: HashMap<String, HashSet<String>> = HashMap::new();
+        for stop in &stops {
+            let code = non_empty(stop.stop_code.clone()).unwrap_or_else(|| stop.stop_id.clone());
+            stop_ids
+                .entry(code)
+                .or_default()
+                .insert(stop.stop_id.clone());
+            stop_ids
+                .entry(stop.stop_id.clone())
+                .or_default()
+                .insert(stop.stop_id.clone());
+        }
+        // a station's code should also match arrivals at its platforms
+        let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+        for stop in &stops {
+            if let Some(parent) = stop.parent_station.as_deref().filter(|p| !p.is_empty()) {
+                children.entry(parent).or_default().push(&stop.stop_id);
+            }
+        }
+        for ids in stop_ids.values_mut() {
+            let platforms = ids
+                .iter()
+                .filter_map(|id| children.get(id.as_str()))
+                .flatten()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>();
+            ids.extend(platforms);
+        }
+
+        let routes: HashMap<String, String> =
+            read_records::<RouteRecord, _>(&mut archive, "routes.txt")?
+                .into_iter()
+                .map(|route| {
+                    let name = non_empty(route.route_short_name)
+                        .or(non_empty(route.route_long_name))
+                        .unwrap_or_else(|| route.route_id.clone());
+                    (route.route_id, name)
+                })
+                .collect();
+
+        let trips = read_records::<TripRecord, _>(&mut archive, "trips.txt")?
+            .into_iter()
+            .map(|trip| {
+                let data = Trip {
+                    route: routes.get(&trip.route_id).cloned().unwrap_or(trip.route_id),
+                    headsign: non_empty(trip.trip_headsign).unwrap_or_default(),
+                };
+                (trip.trip_id, data)
+            })
+            .collect();
+
+        let mut stop_times: HashMap<String, Vec<StopTime>> = HashMap::new();
+        for record in read_records::<StopTimeRecord, _>(&mut archive, "stop_times.txt")? {
+            let arrival = non_empty(record.arrival_time)
+                .or(non_empty(record.departure_time))
+                .and_then(|time| parse_gtfs_time(&time));
+            stop_times
+                .entry(record.trip_id)
+                .or_default()
+                .push(StopTime {
+                    stop_sequence: record.stop_sequence,
+                    stop_id: record.stop_id,
+                    arrival,
+                    headsign: non_empty(record.stop_headsign),
+                });
+        }
+        for times in stop_times.values_mut() {
+            times.sort_by_key(|time| time.stop_sequence);
+        }
+
+        Ok(Self {
+            timezone,
+            stop_ids,
+            trips,
+            stop_times,
+        })
+    }
+
+    /// The instant a service day's GTFS times count from: noon minus 12 hours, local time.
+    fn service_day_start(&self, date: NaiveDate) -> Option<DateTime<Utc>> {
+        let noon = self
+            .timezone
+            .from_local_datetime(&date.and_time(NaiveTime::from_hms_opt(12, 0, 0)?))
+            .earliest()?;
+        Some(noon.with_timezone(&Utc) - Duration::try_hours(12)?)
+    }
+
+    /// Scheduled time of a stop on a trip, using the trip's start date if the feed gives one,
+    /// otherwise whichever of yesterday or today puts it closest to now.
+    fn scheduled_time(
+        &self,
+        start_date: Option<&str>,
+        stop_time: &StopTime,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let offset = Duration::try_seconds(stop_time.arrival? as i64)?;
+
+        if let Some(date) = start_date.and_then(|d| NaiveDate::parse_from_str(d, "%Y%m%d").ok()) {
+            return Some(self.service_day_start(date)? + offset);
+        }
+
+        let today = now.with_timezone(&self.timezone).date_naive();
+        [today.pred_opt()?, today]
+            .into_iter()
+            .filter_map(|date| Some(self.service_day_start(date)? + offset))
+            .min_by_key(|time| (*time - now).num_seconds().abs())
+    }
+
+    /// Predicted arrival at `stop_time`, applying the update for that stop if there is one,
+    /// otherwise propagating the delay from the closest earlier stop as GTFS-Realtime specifies.
+    /// Earlier stops that are skipped or have no times are passed over, and without any the
+    /// trip's own delay is applied to the timetable.
+    fn predicted_time(
+        &self,
+        update: &TripUpdate,
+        trip_times: &[StopTime],
+        stop_time: &StopTime,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let start_date = update.trip.start_date.as_deref();
+        let scheduled = |stop_time: &StopTime| self.scheduled_time(start_date, stop_time, now);
+
+        let sequence_of = |stop_update: &StopTimeUpdate| {
+            stop_update.stop_sequence.or_else(|| {
+                let stop_id = stop_update.stop_id.as_deref()?;
+                trip_times
+                    .iter()
+                    .find(|time| time.stop_id == stop_id)
+                    .map(|time| time.stop_sequence)
+            })
+        };
+
+        // Walk back from this stop to the closest update that says where the vehicle is
+        let earlier_updates = update
+            .stop_time_update
+            .iter()
+            .filter_map(|stop_update| Some((sequence_of(stop_update)?, stop_update)))
+            .filter(|(sequence, _)| *sequence <= stop_time.stop_sequence)
+            .sorted_by_key(|(sequence, _)| Reverse(*sequence));
+
+        for (sequence, stop_update) in earlier_updates {
+            let is_this_stop = sequence == stop_time.stop_sequence;
+            match stop_update.schedule_relationship() {
+                StopScheduleRelationship::Skipped if is_this_stop => return None,
+                // the vehicle doesn't stop there, so it says nothing about the delay
+                StopScheduleRelationship::Skipped => continue,
+                StopScheduleRelationship::NoData => return scheduled(stop_time),
+                _ => (),
+            }
+
+            let event = if is_this_stop {
+                stop_update
+                    .arrival
+                    .as_ref()
+                    .or(stop_update.departure.as_ref())
+            } else {
+                // the vehicle leaves an earlier stop at its departure time
+                stop_update
+                    .departure
+                    .as_ref()
+                    .or(stop_update.arrival.as_ref())
+            };
+            let Some(event) = event else {
+                continue;
+            };
+
+            if let (Some(time), true) = (event.time, is_this_stop) {
+                return Utc.timestamp_opt(time, 0).single();
+            }
+            let delay = match (event.delay, event.time) {
+                (Some(delay), _) => Duration::try_seconds(delay as i64),
+                (None, Some(time)) => trip_times
+                    .iter()
+                    .find(|t| t.stop_sequence == sequence)
+                    .and_then(scheduled)
+                    .and_then(|updated_stop| Some(Utc.timestamp_opt(time, 0).single()? - updated_stop)),
+                (None, None) => None,
+            };
+            if let Some(delay) = delay {
+                return Some(scheduled(stop_time)? + delay);
+            }
+        }
+
+        let delay = Duration::try_seconds(update.delay.unwrap_or(0) as i64)?;
+        Some(scheduled(stop_time)? + delay)
+    }
+
+    fn arrivals(
+        &self,
+        feed: &FeedMessage,
+        stop_code: &str,
+        now: DateTime<Utc>,
+    ) -> Vec<RouteArrivals> {
+        let Some(stop_ids) = self.stop_ids.get(stop_code) else {
+            return vec![];
+        };
+
+        let mut arrivals: BTreeMap<(String, String), Vec<DateTime<Utc>>> = BTreeMap::new();
+
+        for entity in &feed.entity {
+            if entity.is_deleted == Some(true) {
+                continue;
+            }
+            let Some(update) = &entity.trip_update else {
+                continue;
+            };
+            if matches!(
+                update.trip.schedule_relationship(),
+                TripScheduleRelationship::Canceled | TripScheduleRelationship::Deleted
+            ) {
+                continue;
+            }
+
+            let Some(trip_id) = update.trip.trip_id.as_deref() else {
+                continue;
+            };
+            let (Some(trip), Some(trip_times)) =
+                (self.trips.get(trip_id), self.stop_times.get(trip_id))
+            else {
+                continue;
+            };
+
+            for stop_time in trip_times
+                .iter()
+                .filter(|time| stop_ids.contains(&time.stop_id))
+            {
+                let Some(time) = self.predicted_time(update, trip_times, stop_time, now) else {
+                    continue;
+                };
+                if time < now {
+                    continue;
+                }
+                let headsign = stop_time
+                    .headsign
+                    .clone()
+                    .unwrap_or_else(|| trip.headsign.clone());
+                arrivals
+                    .entry((trip.route.clone(), headsign))
+                    .or_default()
+                    .push(time);
+            }
+        }
+
+        arrivals
+            .into_iter()
+            .map(|((route, headsign), mut arrival_times)| {
+                arrival_times.sort();
+                RouteArrivals {
+                    route,
+                    headsign,
+                    arrival_times,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Arrivals from a local static GTFS zip and a GTFS-Realtime TripUpdates feed.
+pub struct GtfsRealtime {
+    schedule: Schedule,
+    trip_updates: String,
+    headers: Vec<(String, String)>,
+}
+
+impl GtfsRealtime {
+    /// `trip_updates` is either an `http(s)://` URL or a path to a protobuf file.
+    ///
+    /// The whole timetable is loaded into memory up front, so this can take a while for large agencies.
+    pub fn new(schedule_zip: impl AsRef<Path>, trip_updates: &str) -> Result<Self> {
+        let gtfs = Self {
+            schedule: Schedule::load(schedule_zip.as_ref())?,
+            trip_updates: trip_updates.to_string(),
+            headers: vec![],
+        };
+        Ok(gtfs)
+    }
+
+    /// Sends a header with feed requests, e.g. for an API key.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    async fn fetch_trip_updates(&self) -> Result<FeedMessage> {
+        let data = if self.trip_updates.starts_with("http://")
+            || self.trip_updates.starts_with("https://")
+        {
+            let request = self.headers.iter().fold(
+                reqwest::Client::new().get(&self.trip_updates),
+                |request, (name, value)| request.header(name, value),
+            );
+            request
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?
+                .to_vec()
+        } else {
+            tokio::fs::read(&self.trip_updates).await?
+        };
+
+        Ok(FeedMessage::decode(data.as_slice())?)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl ArrivalsSource for GtfsRealtime {
+    async fn stop_arrivals(&self, stop_code: &str) -> Result<Vec<RouteArrivals>> {
+        if !self.schedule.stop_ids.contains_key(stop_code) {
+            return Ok(vec![]);
+        }
+
+        let feed = self.fetch_trip_updates().await?;
+        Ok(self.schedule.arrivals(&feed, stop_code, Utc::now()))
+    }
+
+    fn cache_key(&self) -> String {
+        format!("gtfs:{}", self.trip_updates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/gtfs");
+
+    fn local(hour: u32, min: u32) -> DateTime<Utc> {
+        chrono_tz::Pacific::Auckland
+            .with_ymd_and_hms(2024, 4, 15, hour, min, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn arrivals_at(stop_code: &str) -> Vec<RouteArrivals> {
+        arrivals_from("trip_updates.pb", stop_code)
+    }
+
+    fn arrivals_from(trip_updates: &str, stop_code: &str) -> Vec<RouteArrivals> {
+        let gtfs = GtfsRealtime::new(
+            format!("{FIXTURES}/schedule.zip"),
+            &format!("{FIXTURES}/{trip_updates}"),
+        )
+        .unwrap();
+        let data = std::fs::read(&gtfs.trip_updates).unwrap();
+        let feed = FeedMessage::decode(data.as_slice()).unwrap();
+        gtfs.schedule.arrivals(&feed, stop_code, local(8, 0))
+    }
+
+    #[test]
+    fn applies_trip_updates_to_station_platforms() {
+        let arrivals = arrivals_at("1000");
+
+        assert_eq!(arrivals.len(), 2);
+        assert_eq!(arrivals[0].route, "NX1");
+        assert_eq!(arrivals[0].headsign, "HIBISCUS COAST");
+        // T1 has a prediction for this stop, T2 is 3 minutes late from its first stop,
+        // and T4 is cancelled
+        assert_eq!(arrivals[0].arrival_times, vec![local(8, 12), local(8, 28)]);
+        assert_eq!(arrivals[1].route, "Western Line");
+        assert_eq!(arrivals[1].arrival_times, vec![local(8, 7)]);
+    }
+
+    #[test]
+    fn propagates_delay_and_drops_departed() {
+        let arrivals = arrivals_at("2000");
+
+        assert_eq!(arrivals.len(), 1);
+        assert_eq!(arrivals[0].arrival_times, vec![local(8, 22)]);
+        assert!(arrivals_at("9999").is_empty());
+    }
+
+    #[test]
+    fn passes_over_skipped_stops() {
+        // T1 leaves its first stop 2 minutes late, then skips Britomart
+        assert!(arrivals_from("skipped_stop.pb", "1000").is_empty());
+        let arrivals = arrivals_from("skipped_stop.pb", "2000");
+        assert_eq!(arrivals.len(), 1);
+        assert_eq!(arrivals[0].arrival_times, vec![local(8, 22)]);
+    }
+}
@@ -0,0 +1,169 @@
+//! Entity states from [Home Assistant](https://developers.home-assistant.io/docs/api/rest/)'s
+//! REST API, such as indoor temperatures and whether doors are open.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::screen::Result;
+
+/// The state of one entity, independent of where it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityState {
+    pub entity_id: String,
+    /// As reported, e.g. `21.5`, `on` or `unavailable`
+    pub state: String,
+    /// The entity's friendly name, if it has one
+    pub name: Option<String>,
+    /// e.g. `°C` or `%`
+    pub unit: Option<String>,
+    /// What kind of sensor this is, e.g. `temperature` or `door`
+    pub device_class: Option<String>,
+}
+
+impl EntityState {
+    /// The state as it should read on screen: doors and windows are open or closed rather than
+    /// on or off.
+    pub fn display_state(&self) -> String {
+        let opening = matches!(
+            self.device_class.as_deref(),
+            Some("door" | "garage_door" | "opening" | "window")
+        );
+        match (opening, self.state.as_str()) {
+            (true, "on") => "open".into(),
+            (true, "off") => "closed".into(),
+            (_, state) => state.into(),
+        }
+    }
+}
+
+/// Something that can look up entity states by ID.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait EntitySource: Send + Sync {
+    async fn entity_state(&self, entity_id: &str) -> Result<EntityState>;
+}
+
+#[derive(Deserialize)]
+struct StateResponse {
+    entity_id: String,
+    state: String,
+    #[serde(default)]
+    attributes: HashMap<String, serde_json::Value>,
+}
+
+/// A Home Assistant instance, authenticated with a long-lived access token from the user's
+/// profile page.
+pub struct HomeAssistant {
+    url: String,
+    token: String,
+}
+
+impl HomeAssistant {
+    /// `url` is where Home Assistant is served, e.g. `http://homeassistant.local:8123`.
+    pub fn new(url: &str, token: &str) -> Self {
+        HomeAssistant {
+            url: url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl EntitySource for HomeAssistant {
+    async fn entity_state(&self, entity_id: &str) -> Result<EntityState> {
+        let response: StateResponse = reqwest::Client::new()
+            .get(format!("{}/api/states/{}", self.url, entity_id))
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let attribute = |name: &str| {
+            response
+                .attributes
+                .get(name)
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_string())
+        };
+        Ok(EntityState {
+            name: attribute("friendly_name"),
+            unit: attribute("unit_of_measurement"),
+            device_class: attribute("device_class"),
+            entity_id: response.entity_id,
+            state: response.state,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Serves one canned response per request, checking the token.
+    fn mock_home_assistant(states: &'static [(&'static str, &'static str)]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines().map(|line| line.unwrap());
+                let request = lines.next().unwrap();
+                let headers: Vec<String> = lines.take_while(|line| !line.is_empty()).collect();
+
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let authorized = headers.iter().any(|header| header.eq_ignore_ascii_case("authorization: Bearer secret"));
+                let body = states
+                    .iter()
+                    .find(|(id, _)| path == format!("/api/states/{id}"))
+                    .map(|(_, body)| *body);
+                let (status, body) = match (authorized, body) {
+                    (false, _) => ("401 Unauthorized", "401: Unauthorized"),
+                    (true, Some(body)) => ("200 OK", body),
+                    (true, None) => ("404 Not Found", r#"{"message": "Entity not found."}"#),
+                };
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn fetches_entity_states() {
+        let url = mock_home_assistant(&[
+            (
+                "sensor.lounge_temperature",
+                r#"{"entity_id": "sensor.lounge_temperature", "state": "21.5", "attributes": {"unit_of_measurement": "°C", "device_class": "temperature", "friendly_name": "Lounge"}}"#,
+            ),
+            (
+                "binary_sensor.front_door",
+                r#"{"entity_id": "binary_sensor.front_door", "state": "on", "attributes": {"device_class": "door", "friendly_name": "Front door"}}"#,
+            ),
+        ]);
+        let home_assistant = HomeAssistant::new(&format!("{url}/"), "secret");
+
+        let lounge = home_assistant.entity_state("sensor.lounge_temperature").await.unwrap();
+        assert_eq!(lounge.name.as_deref(), Some("Lounge"));
+        assert_eq!((lounge.display_state(), lounge.unit.as_deref()), ("21.5".into(), Some("°C")));
+
+        let door = home_assistant.entity_state("binary_sensor.front_door").await.unwrap();
+        assert_eq!(door.display_state(), "open");
+
+        assert!(home_assistant.entity_state("sensor.missing").await.is_err());
+        let unauthorized = HomeAssistant::new(&url, "wrong");
+        assert!(unauthorized.entity_state("binary_sensor.front_door").await.is_err());
+    }
+}
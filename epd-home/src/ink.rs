@@ -0,0 +1,202 @@
+//! Pixel colours, and the sets of them that different panels can show.
+
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::screen::Error;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Ink {
+    #[default]
+    White,
+    Black,
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Orange,
+    /// A level between black and white, 0 to 255, on greyscale panels.
+    Gray(u8),
+}
+
+impl Ink {
+    /// The colour as drawn in the template.
+    pub fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            Ink::White => (255, 255, 255),
+            Ink::Black => (0, 0, 0),
+            Ink::Red => (255, 0, 0),
+            Ink::Yellow => (255, 255, 0),
+            Ink::Green => (0, 255, 0),
+            Ink::Blue => (0, 0, 255),
+            Ink::Orange => (255, 128, 0),
+            Ink::Gray(level) => (level, level, level),
+        }
+    }
+
+    pub fn luma(self) -> u8 {
+        let (r, g, b) = self.rgb();
+        ((r as u32 * 2126 + g as u32 * 7152 + b as u32 * 722) / 10000) as u8
+    }
+}
+
+/// The third colour of a three-colour panel.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Accent {
+    Red,
+    Yellow,
+}
+
+impl Accent {
+    pub fn ink(self) -> Ink {
+        match self {
+            Accent::Red => Ink::Red,
+            Accent::Yellow => Ink::Yellow,
+        }
+    }
+}
+
+/// The colours a panel can show.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(try_from = "String")]
+pub enum Colors {
+    #[default]
+    BlackWhite,
+    /// Black, white, and red or yellow
+    ThreeColor(Accent),
+    /// 7-colour ACeP, e.g. Inky Impression and UC8159 based panels
+    Acep,
+    /// E Ink Spectra 6
+    Spectra6,
+    /// Levels of grey at 2 or 4 bits per pixel, e.g. IT8951 based panels
+    Grayscale(u8),
+}
+
+// Colours measured on each panel, from Pimoroni's inky library and community measurements of
+// Spectra 6 panels, in the controller's index order
+const ACEP: &[(Ink, (u8, u8, u8))] = &[
+    (Ink::Black, (57, 48, 57)),
+    (Ink::White, (255, 255, 255)),
+    (Ink::Green, (58, 91, 70)),
+    (Ink::Blue, (61, 59, 94)),
+    (Ink::Red, (156, 72, 75)),
+    (Ink::Yellow, (208, 190, 71)),
+    (Ink::Orange, (177, 106, 73)),
+];
+
+// Index 4 is unused
+const SPECTRA_6: &[(Ink, (u8, u8, u8))] = &[
+    (Ink::Black, (25, 30, 33)),
+    (Ink::White, (232, 232, 232)),
+    (Ink::Yellow, (239, 222, 68)),
+    (Ink::Red, (178, 19, 24)),
+    (Ink::Blue, (33, 87, 186)),
+    (Ink::Green, (18, 95, 32)),
+];
+
+impl Colors {
+    /// The inks in the controller's index order, where that matters.
+    pub fn inks(self) -> Vec<Ink> {
+        match self {
+            Colors::BlackWhite => vec![Ink::Black, Ink::White],
+            Colors::ThreeColor(accent) => vec![Ink::Black, Ink::White, accent.ink()],
+            Colors::Acep => ACEP.iter().map(|(ink, _)| *ink).collect(),
+            Colors::Spectra6 => SPECTRA_6.iter().map(|(ink, _)| *ink).collect(),
+            Colors::Grayscale(bits) => {
+                let max = (1u16 << bits) - 1;
+                (0..=max)
+                    .map(|level| match level {
+                        0 => Ink::Black,
+                        level if level == max => Ink::White,
+                        level => Ink::Gray((level * 255 / max) as u8),
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Colours to dither against. For colour panels, these are halfway between the measured and
+    /// nominal colours, as the inky library does by default, which keeps photos natural without
+    /// speckling the edges of black text with yellow.
+    pub(crate) fn palette(self) -> Vec<(Ink, (u8, u8, u8))> {
+        let measured = match self {
+            Colors::Acep => ACEP,
+            Colors::Spectra6 => SPECTRA_6,
+            _ => return self.inks().into_iter().map(|ink| (ink, ink.rgb())).collect(),
+        };
+        let blend = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+        measured
+            .iter()
+            .map(|&(ink, (r, g, b))| {
+                let (r0, g0, b0) = ink.rgb();
+                (ink, (blend(r, r0), blend(g, g0), blend(b, b0)))
+            })
+            .collect()
+    }
+
+    /// The colour for elements the template marks as accented.
+    pub fn accent(self) -> Ink {
+        match self {
+            Colors::BlackWhite | Colors::Grayscale(_) => Ink::Black,
+            Colors::ThreeColor(accent) => accent.ink(),
+            Colors::Acep | Colors::Spectra6 => Ink::Red,
+        }
+    }
+
+    /// The controller's index for `ink`, or white's if the panel can't show it. For greyscale,
+    /// this is the nearest level, from 0 for black.
+    pub fn index(self, ink: Ink) -> u8 {
+        if let Colors::Grayscale(bits) = self {
+            let max = (1u32 << bits) - 1;
+            return ((ink.luma() as u32 * max + 127) / 255) as u8;
+        }
+        let position = self.inks().iter().position(|i| *i == ink).unwrap_or(1);
+        match self {
+            Colors::Spectra6 => [0, 1, 2, 3, 5, 6][position],
+            _ => position as u8,
+        }
+    }
+}
+
+impl FromStr for Colors {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bw" => Ok(Colors::BlackWhite),
+            "bwr" | "red" => Ok(Colors::ThreeColor(Accent::Red)),
+            "bwy" | "yellow" => Ok(Colors::ThreeColor(Accent::Yellow)),
+            "acep" | "7" => Ok(Colors::Acep),
+            "spectra6" | "6" => Ok(Colors::Spectra6),
+            "gray2" | "grey2" => Ok(Colors::Grayscale(2)),
+            "gray4" | "grey4" => Ok(Colors::Grayscale(4)),
+            _ => Err(Error::UnknownColors(s.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for Colors {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexes_in_controller_order() {
+        assert_eq!(Colors::Acep.index(Ink::Orange), 6);
+        assert_eq!(Colors::Spectra6.index(Ink::Blue), 5);
+        assert_eq!(Colors::Spectra6.index(Ink::Green), 6);
+        // Spectra 6 has no orange
+        assert_eq!(Colors::Spectra6.index(Ink::Orange), 1);
+        assert_eq!("bwr".parse::<Colors>().unwrap(), Colors::ThreeColor(Accent::Red));
+        assert_eq!(Colors::Grayscale(2).index(Ink::Gray(170)), 2);
+        assert_eq!(Colors::Grayscale(4).index(Ink::White), 15);
+    }
+}
@@ -0,0 +1,345 @@
+//! Screens composed of widgets on a grid, instead of the fixed home screen layout.
+//!
+//! Each widget draws its own SVG fragment at a natural size, which is scaled to fit the cells it
+//! covers. Widgets only fetch the data they show, so a screen with just a clock and the weather
+//! never asks for arrivals.
+
+use askama::Template;
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+use crate::screen::{
+    filters, ArrivalTime, ArrivalsSection, CalendarSection, EntitiesSection, Error,
+    HomeSvgTemplate, Result, WeatherSection,
+};
+
+/// What a cell shows.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Widget {
+    /// The current time
+    Clock,
+    /// Current conditions and temperature
+    Weather,
+    /// The next few hours, with sunrise or sunset
+    Forecast,
+    /// Departures from the screen's stops
+    Arrivals,
+    /// Today's and tomorrow's events from the screen's calendars
+    Calendar,
+    /// States of the screen's Home Assistant entities
+    Entities,
+}
+
+impl Widget {
+    fn needs_weather(&self) -> bool {
+        matches!(self, Widget::Weather | Widget::Forecast)
+    }
+
+    fn needs_arrivals(&self) -> bool {
+        matches!(self, Widget::Arrivals)
+    }
+
+    fn needs_calendar(&self) -> bool {
+        matches!(self, Widget::Calendar)
+    }
+
+    fn needs_entities(&self) -> bool {
+        matches!(self, Widget::Entities)
+    }
+
+    /// The widget's SVG fragment, and the size it's drawn at.
+    fn render(&self, model: &HomeSvgTemplate) -> Result<(String, (u32, u32))> {
+        let weather = &model.weather;
+        let arrivals = &model.arrivals;
+        let (svg, size) = match self {
+            Widget::Clock => (ClockWidget { time: &model.time }.render(), (360, 130)),
+            Widget::Weather => (WeatherWidget { weather }.render(), (420, 150)),
+            Widget::Forecast => (ForecastWidget { weather }.render(), (340, 300)),
+            Widget::Arrivals => (ArrivalsWidget { arrivals }.render(), (440, 340)),
+            Widget::Calendar => (CalendarWidget { calendar: &model.calendar }.render(), (440, 360)),
+            Widget::Entities => (EntitiesWidget { entities: &model.entities }.render(), (440, 360)),
+        };
+        Ok((svg?, size))
+    }
+}
+
+/// A widget and the cells it covers, counting from the top left.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Placement {
+    #[serde(flatten)]
+    widget: Widget,
+    column: u32,
+    row: u32,
+    #[serde(default = "one")]
+    column_span: u32,
+    #[serde(default = "one")]
+    row_span: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+impl Placement {
+    pub fn new(widget: Widget, column: u32, row: u32) -> Self {
+        Placement {
+            widget,
+            column,
+            row,
+            column_span: 1,
+            row_span: 1,
+        }
+    }
+
+    /// Cover `columns` x `rows` cells instead of one.
+    pub fn with_span(mut self, columns: u32, rows: u32) -> Self {
+        self.column_span = columns;
+        self.row_span = rows;
+        self
+    }
+
+    /// Whether the two cover any of the same cells. Only meaningful once both fit on the grid.
+    fn overlaps(&self, other: &Placement) -> bool {
+        self.column < other.column + other.column_span
+            && other.column < self.column + self.column_span
+            && self.row < other.row + other.row_span
+            && other.row < self.row + self.row_span
+    }
+}
+
+/// A grid of equally sized cells, with widgets placed on it. In TOML:
+///
+/// ```toml
+/// columns = 2
+/// rows = 3
+///
+/// [[widgets]]
+/// kind = "clock"
+/// column = 1
+/// row = 0
+///
+/// [[widgets]]
+/// kind = "arrivals"
+/// column = 1
+/// row = 1
+/// row_span = 2
+/// ```
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Layout {
+    columns: u32,
+    rows: u32,
+    #[serde(default)]
+    widgets: Vec<Placement>,
+}
+
+impl Layout {
+    pub fn new(columns: u32, rows: u32) -> Self {
+        Layout {
+            columns,
+            rows,
+            widgets: vec![],
+        }
+    }
+
+    pub fn with_widget(mut self, placement: Placement) -> Self {
+        self.widgets.push(placement);
+        self
+    }
+
+    pub(crate) fn shows_clock(&self) -> bool {
+        self.widgets.iter().any(|placement| placement.widget == Widget::Clock)
+    }
+
+    pub(crate) fn needs_weather(&self) -> bool {
+        self.widgets.iter().any(|placement| placement.widget.needs_weather())
+    }
+
+    pub(crate) fn needs_arrivals(&self) -> bool {
+        self.widgets.iter().any(|placement| placement.widget.needs_arrivals())
+    }
+
+    pub(crate) fn needs_calendar(&self) -> bool {
+        self.widgets.iter().any(|placement| placement.widget.needs_calendar())
+    }
+
+    pub(crate) fn needs_entities(&self) -> bool {
+        self.widgets.iter().any(|placement| placement.widget.needs_entities())
+    }
+
+    /// Every widget has to fit on the grid, without covering another one. Worth checking when the
+    /// layout is loaded, so a bad one is caught before anything is rendered.
+    pub fn validate(&self) -> Result<()> {
+        if self.columns == 0 || self.rows == 0 {
+            return Err(Error::InvalidLayout("the grid is empty".into()));
+        }
+        for placement in &self.widgets {
+            let columns = placement.column.checked_add(placement.column_span);
+            let rows = placement.row.checked_add(placement.row_span);
+            let fits = placement.column_span > 0
+                && placement.row_span > 0
+                && columns.is_some_and(|end| end <= self.columns)
+                && rows.is_some_and(|end| end <= self.rows);
+            if !fits {
+                return Err(Error::InvalidLayout(format!(
+                    "{:?} at column {}, row {} is off the {}x{} grid",
+                    placement.widget, placement.column, placement.row, self.columns, self.rows
+                )));
+            }
+        }
+        for (i, placement) in self.widgets.iter().enumerate() {
+            if let Some(other) = self.widgets[..i].iter().find(|other| placement.overlaps(other)) {
+                return Err(Error::InvalidLayout(format!(
+                    "{:?} at column {}, row {} overlaps {:?} at column {}, row {}",
+                    placement.widget, placement.column, placement.row, other.widget, other.column, other.row
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lays the widgets out on a `width` x `height` canvas.
+pub(crate) fn render(layout: &Layout, model: &HomeSvgTemplate, (width, height): (u32, u32)) -> Result<String> {
+    layout.validate()?;
+
+    // Cell edges are rounded down, so spans line up with the cells next to them
+    let x = |column: u32| (u64::from(width) * u64::from(column) / u64::from(layout.columns)) as u32;
+    let y = |row: u32| (u64::from(height) * u64::from(row) / u64::from(layout.rows)) as u32;
+
+    let cells = layout
+        .widgets
+        .iter()
+        .map(|placement| {
+            let (content, view_box) = placement.widget.render(model)?;
+            let (left, top) = (x(placement.column), y(placement.row));
+            Ok(Cell {
+                x: left,
+                y: top,
+                width: x(placement.column + placement.column_span) - left,
+                height: y(placement.row + placement.row_span) - top,
+                view_box,
+                content,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    let svg = LayoutSvgTemplate {
+        accent: &model.accent,
+        width,
+        height,
+        cells,
+    };
+    Ok(svg.render()?)
+}
+
+struct Cell {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    view_box: (u32, u32),
+    content: String,
+}
+
+#[derive(Template)]
+#[template(path = "layout.svg")]
+struct LayoutSvgTemplate<'a> {
+    accent: &'a str,
+    width: u32,
+    height: u32,
+    cells: Vec<Cell>,
+}
+
+#[derive(Template)]
+#[template(path = "widgets/clock.svg")]
+struct ClockWidget<'a> {
+    time: &'a DateTime<Tz>,
+}
+
+#[derive(Template)]
+#[template(path = "widgets/weather.svg")]
+struct WeatherWidget<'a> {
+    weather: &'a Option<WeatherSection>,
+}
+
+#[derive(Template)]
+#[template(path = "widgets/forecast.svg")]
+struct ForecastWidget<'a> {
+    weather: &'a Option<WeatherSection>,
+}
+
+#[derive(Template)]
+#[template(path = "widgets/arrivals.svg")]
+struct ArrivalsWidget<'a> {
+    arrivals: &'a Option<ArrivalsSection>,
+}
+
+#[derive(Template)]
+#[template(path = "widgets/calendar.svg")]
+struct CalendarWidget<'a> {
+    calendar: &'a Option<CalendarSection>,
+}
+
+#[derive(Template)]
+#[template(path = "widgets/entities.svg")]
+struct EntitiesWidget<'a> {
+    entities: &'a Option<EntitiesSection>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_toml() {
+        let layout: Layout = toml::from_str(
+            r#"
+            columns = 2
+            rows = 3
+
+            [[widgets]]
+            kind = "clock"
+            column = 1
+            row = 0
+
+            [[widgets]]
+            kind = "arrivals"
+            column = 1
+            row = 1
+            row_span = 2
+            "#,
+        )
+        .unwrap();
+
+        let expected = Layout::new(2, 3)
+            .with_widget(Placement::new(Widget::Clock, 1, 0))
+            .with_widget(Placement::new(Widget::Arrivals, 1, 1).with_span(1, 2));
+        assert_eq!(layout, expected);
+        assert!(layout.needs_arrivals());
+        assert!(!layout.needs_weather());
+    }
+
+    #[test]
+    fn rejects_widgets_off_the_grid() {
+        let layout = Layout::new(2, 2).with_widget(Placement::new(Widget::Clock, 1, 1).with_span(2, 1));
+        assert!(matches!(layout.validate(), Err(Error::InvalidLayout(_))));
+        assert!(matches!(Layout::new(0, 1).validate(), Err(Error::InvalidLayout(_))));
+    }
+
+    #[test]
+    fn rejects_overlapping_widgets() {
+        let layout = Layout::new(2, 3)
+            .with_widget(Placement::new(Widget::Arrivals, 1, 0).with_span(1, 3))
+            .with_widget(Placement::new(Widget::Clock, 0, 0))
+            .with_widget(Placement::new(Widget::Weather, 0, 1).with_span(2, 1));
+        assert!(matches!(layout.validate(), Err(Error::InvalidLayout(_))));
+
+        let side_by_side = Layout::new(2, 3)
+            .with_widget(Placement::new(Widget::Arrivals, 1, 0).with_span(1, 3))
+            .with_widget(Placement::new(Widget::Clock, 0, 0))
+            .with_widget(Placement::new(Widget::Weather, 0, 1).with_span(1, 2));
+        assert!(side_by_side.validate().is_ok());
+    }
+}
@@ -1,4 +1,18 @@
+pub mod cache;
+pub mod calendar;
+pub mod frame;
+#[cfg(feature = "gtfs")]
+pub mod gtfs;
+pub mod home_assistant;
+pub mod ink;
+pub mod layout;
+pub mod night;
+pub mod partial;
+pub mod raw;
+pub mod refresh;
 pub mod screen;
 mod dither;
-mod transport;
-mod weather;
+#[cfg(feature = "templates")]
+mod template;
+pub mod transport;
+pub mod weather;
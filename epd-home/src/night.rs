@@ -0,0 +1,93 @@
+//! Quiet hours, when nobody's looking at the screen. Instead of the usual screen, it shows when
+//! they end with the forecast and first departure for then, and isn't due to refresh until they
+//! end, saving both the panel and the sources it polls.
+
+use askama::Template;
+use chrono::{DateTime, Duration, NaiveTime};
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+use crate::screen::{filters, local_time, ArrivalData, ArrivalTime, WeatherData};
+
+/// A daily period in the screen's timezone, which may run past midnight. In TOML:
+///
+/// ```toml
+/// start = "23:00"
+/// end = "06:00"
+/// ```
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        QuietHours { start, end }
+    }
+
+    /// When the quiet hours `time` is in end, or `None` if it isn't in them.
+    pub fn ends_at(&self, time: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let now = time.time();
+        let quiet = match self.start <= self.end {
+            true => self.start <= now && now < self.end,
+            false => self.start <= now || now < self.end,
+        };
+        if !quiet {
+            return None;
+        }
+
+        let date = match now < self.end {
+            true => time.date_naive(),
+            false => time.date_naive() + Duration::days(1),
+        };
+        Some(local_time(&time.timezone(), date.and_time(self.end)))
+    }
+}
+
+/// Each part is `None` if its data couldn't be gathered, or the screen doesn't show it.
+#[derive(Template)]
+#[template(path = "night.svg")]
+pub(crate) struct NightSvgTemplate {
+    pub(crate) accent: String,
+    /// When the quiet hours end
+    pub(crate) until: DateTime<Tz>,
+    pub(crate) forecast: Option<WeatherData>,
+    pub(crate) departure: Option<ArrivalData>,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Tz> {
+        chrono_tz::Europe::London.with_ymd_and_hms(2024, 6, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn runs_past_midnight() {
+        let quiet_hours: QuietHours = toml::from_str("start = \"23:00\"\nend = \"06:00\"").unwrap();
+
+        assert_eq!(quiet_hours.ends_at(at(1, 22, 59)), None);
+        assert_eq!(quiet_hours.ends_at(at(1, 23, 0)), Some(at(2, 6, 0)));
+        assert_eq!(quiet_hours.ends_at(at(2, 3, 0)), Some(at(2, 6, 0)));
+        assert_eq!(quiet_hours.ends_at(at(2, 6, 0)), None);
+
+        let afternoon = QuietHours::new(NaiveTime::from_hms_opt(13, 0, 0).unwrap(), NaiveTime::from_hms_opt(15, 0, 0).unwrap());
+        assert_eq!(afternoon.ends_at(at(1, 14, 0)), Some(at(1, 15, 0)));
+        assert_eq!(afternoon.ends_at(at(1, 23, 30)), None);
+    }
+
+    #[test]
+    fn ends_an_hour_later_when_the_clocks_skip_the_end() {
+        let auckland = chrono_tz::Pacific::Auckland;
+        let quiet_hours: QuietHours = toml::from_str("start = \"23:00\"\nend = \"02:30\"").unwrap();
+
+        // The clocks go forward from 2:00 to 3:00
+        let night = auckland.with_ymd_and_hms(2024, 9, 29, 1, 0, 0).unwrap();
+        let end = auckland.with_ymd_and_hms(2024, 9, 29, 3, 30, 0).unwrap();
+        assert_eq!(quiet_hours.ends_at(night), Some(end));
+    }
+}
@@ -0,0 +1,137 @@
+//! Changed regions between two frames, for panels' partial refresh modes.
+
+use crate::{
+    frame::Frame,
+    raw::{self, RawOptions},
+};
+
+/// A region of the frame, in pixels. Horizontal edges are always multiples of 8, since
+/// controllers address their RAM in whole bytes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Columns closer than this are drawn in the same rectangle.
+const COLUMN_GAP: usize = 16;
+
+/// Finds rectangles covering every pixel that differs between `prev` and `next`.
+///
+/// Consecutive changed rows are grouped into bands, and each band is split where there's a gap
+/// of unchanged columns, so that e.g. the clock and a countdown on the same rows are updated
+/// separately. If the frames are different sizes or colours, the whole of `next` is returned.
+pub fn changed_rects(prev: &Frame, next: &Frame) -> Vec<Rect> {
+    let (width, height) = (next.width() as usize, next.height() as usize);
+    let same_format = (prev.width(), prev.height(), prev.colors()) == (next.width(), next.height(), next.colors());
+    if !same_format {
+        return vec![Rect { x: 0, y: 0, width: width.div_ceil(8) * 8, height }];
+    }
+
+    let changed = |y: usize, x: usize| prev.get(x as u32, y as u32) != next.get(x as u32, y as u32);
+    let row_changed = |y: usize| (0..width).any(|x| changed(y, x));
+
+    let mut rects = Vec::new();
+    let mut y = 0;
+    while y < height {
+        if !row_changed(y) {
+            y += 1;
+            continue;
+        }
+        let top = y;
+        while y < height && row_changed(y) {
+            y += 1;
+        }
+        let band = top..y;
+
+        let columns: Vec<usize> = (0..width)
+            .filter(|&x| band.clone().any(|y| changed(y, x)))
+            .collect();
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for x in columns {
+            match runs.last_mut() {
+                Some((_, end)) if x - *end < COLUMN_GAP => *end = x,
+                _ => runs.push((x, x)),
+            }
+        }
+
+        for (left, right) in runs {
+            // Tighten each run to the rows it changes, since the band may be taller
+            let rows: Vec<usize> = band
+                .clone()
+                .filter(|&y| (left..=right).any(|x| changed(y, x)))
+                .collect();
+            let x = left / 8 * 8;
+            rects.push(Rect {
+                x,
+                y: rows[0],
+                width: (right + 1 - x).div_ceil(8) * 8,
+                height: rows[rows.len() - 1] + 1 - rows[0],
+            });
+        }
+    }
+
+    rects
+}
+
+/// Encodes the parts of `next` that differ from `prev`, or all of it if there's no `prev`.
+///
+/// The result is a little-endian `u16` count of rectangles, then for each one its `x`, `y`,
+/// `width` and `height` as little-endian `u16`s, followed by its pixels packed with
+/// [`raw::pack_for_panel`]. Each rectangle is a whole number of bytes wide, so rows are never
+/// padded, and anything past the edge of the frame is white.
+pub fn encode_diff(prev: Option<&Frame>, next: &Frame, options: &RawOptions) -> Vec<u8> {
+    let rects = match prev {
+        Some(prev) => changed_rects(prev, next),
+        None => changed_rects(&Frame::new(0, 0, next.colors()), next),
+    };
+
+    let mut data = (rects.len() as u16).to_le_bytes().to_vec();
+    for rect in rects {
+        for value in [rect.x, rect.y, rect.width, rect.height] {
+            data.extend((value as u16).to_le_bytes());
+        }
+        let pixels = next.crop(rect.x as u32, rect.y as u32, rect.width as u32, rect.height as u32);
+        data.extend(raw::pack_for_panel(&pixels, options));
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ink::{Colors, Ink};
+
+    #[test]
+    fn finds_separate_changed_regions() {
+        let prev = Frame::new(64, 20, Colors::BlackWhite);
+        let mut next = prev.clone();
+        // A clock digit and a countdown on overlapping rows, and something further down
+        next.set(3, 2, Ink::Black);
+        next.set(10, 4, Ink::Black);
+        next.set(50, 3, Ink::Black);
+        next.set(20, 15, Ink::Black);
+
+        assert_eq!(
+            changed_rects(&prev, &next),
+            [
+                Rect { x: 0, y: 2, width: 16, height: 3 },
+                Rect { x: 48, y: 3, width: 8, height: 1 },
+                Rect { x: 16, y: 15, width: 8, height: 1 },
+            ]
+        );
+        assert!(changed_rects(&next, &next).is_empty());
+    }
+
+    #[test]
+    fn encodes_whole_frame_without_previous() {
+        let next = Frame::from_fn(10, 2, Colors::BlackWhite, |_, _| Ink::Black);
+        let data = encode_diff(None, &next, &RawOptions::default());
+
+        assert_eq!(&data[..10], [1, 0, 0, 0, 0, 0, 16, 0, 2, 0]);
+        assert_eq!(&data[10..], [0b0000_0000, 0b0011_1111, 0b0000_0000, 0b0011_1111]);
+    }
+}
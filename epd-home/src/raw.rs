@@ -0,0 +1,156 @@
+//! Packed framebuffers, for uploading straight into an ePaper controller's RAM.
+
+use serde::Deserialize;
+
+use itertools::Itertools;
+
+use crate::{
+    frame::Frame,
+    ink::{Colors, Ink},
+};
+
+/// Which end of each byte holds the leftmost pixel.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BitOrder {
+    /// Leftmost pixel in bit 7, as most Waveshare and Good Display controllers expect.
+    #[default]
+    Msb,
+    Lsb,
+}
+
+/// How to pack a frame. The defaults match the black/white RAM of common SSD16xx and UC81xx
+/// controllers: most significant bit first, 1 is white, and each row padded to a whole byte.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct RawOptions {
+    pub bit_order: BitOrder,
+    /// Write black as 1 instead of 0.
+    pub invert: bool,
+    /// Start each row on a new byte. Otherwise rows run on from each other, and only the end of
+    /// the frame is padded.
+    pub pad_rows: bool,
+}
+
+impl Default for RawOptions {
+    fn default() -> Self {
+        Self {
+            bit_order: BitOrder::Msb,
+            invert: false,
+            pad_rows: true,
+        }
+    }
+}
+
+/// Packs the black pixels of a frame at 1 bit per pixel.
+pub fn pack(frame: &Frame, options: &RawOptions) -> Vec<u8> {
+    pack_plane(frame, options, |ink| ink == Ink::Black)
+}
+
+/// Packs a frame the way its panel's controller takes it: with [`pack`] for black and white,
+/// [`pack_planes`] for three colours and [`pack_indexed`] for more.
+pub fn pack_for_panel(frame: &Frame, options: &RawOptions) -> Vec<u8> {
+    match frame.colors() {
+        Colors::BlackWhite => pack(frame, options),
+        Colors::ThreeColor(_) => pack_planes(frame, options),
+        Colors::Acep | Colors::Spectra6 | Colors::Grayscale(_) => pack_indexed(frame),
+    }
+}
+
+/// Packs a three-colour frame as two planes, as BWR and BWY controllers take it: the black plane
+/// and then the accent plane, each packed as by [`pack`] with 1 for ink when `invert` is set.
+pub fn pack_planes(frame: &Frame, options: &RawOptions) -> Vec<u8> {
+    let mut data = pack(frame, options);
+    data.extend(pack_plane(frame, options, |ink| !matches!(ink, Ink::Black | Ink::White)));
+    data
+}
+
+/// Packs a frame as controller colour indexes, at 4 bits per pixel for ACeP and Spectra 6 or the
+/// panel's depth for greyscale. The first pixel is in the highest bits of each byte, and rows are
+/// padded to a whole byte with white.
+pub fn pack_indexed(frame: &Frame) -> Vec<u8> {
+    let colors = frame.colors();
+    let bits = match colors {
+        Colors::Grayscale(bits) => bits,
+        _ => 4,
+    };
+    let per_byte = 8 / bits as u32;
+
+    let padded = frame.crop(0, 0, frame.width().div_ceil(per_byte) * per_byte, frame.height());
+    let data = padded
+        .pixels()
+        .map(|ink| colors.index(ink))
+        .chunks(per_byte as usize)
+        .into_iter()
+        .map(|indexes| indexes.fold(0u8, |byte, index| (byte << bits) | index))
+        .collect();
+    data
+}
+
+/// Packs 1 bit per pixel, set where `is_ink` unless inverted.
+fn pack_plane(frame: &Frame, options: &RawOptions, is_ink: impl Fn(Ink) -> bool) -> Vec<u8> {
+    let width = frame.width() as usize;
+    let bits_per_row = if options.pad_rows { width.div_ceil(8) * 8 } else { width };
+    let mut data = vec![0u8; (bits_per_row * frame.height() as usize).div_ceil(8)];
+
+    for (i, ink) in frame.pixels().enumerate() {
+        // Padding bits are left as 0, whatever the polarity
+        if is_ink(ink) != options.invert {
+            continue;
+        }
+        let bit = i / width * bits_per_row + i % width;
+        let shift = match options.bit_order {
+            BitOrder::Msb => 7 - bit % 8,
+            BitOrder::Lsb => bit % 8,
+        };
+        data[bit / 8] |= 1 << shift;
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> Frame {
+        // 10 x 2, black at the start of the first row and the end of the second
+        Frame::from_fn(10, 2, Colors::BlackWhite, |x, y| match (x, y) {
+            (0 | 1, 0) | (9, 1) => Ink::Black,
+            _ => Ink::White,
+        })
+    }
+
+    #[test]
+    fn packs_with_options() {
+        let img = frame();
+
+        let default = pack(&img, &RawOptions::default());
+        assert_eq!(default, [0b0011_1111, 0b1100_0000, 0b1111_1111, 0b1000_0000]);
+
+        let inverted = RawOptions { invert: true, ..Default::default() };
+        assert_eq!(pack(&img, &inverted), [0b1100_0000, 0b0000_0000, 0b0000_0000, 0b0100_0000]);
+
+        let lsb = RawOptions { bit_order: BitOrder::Lsb, invert: true, ..Default::default() };
+        assert_eq!(pack(&img, &lsb), [0b0000_0011, 0b0000_0000, 0b0000_0000, 0b0000_0010]);
+
+        let unpadded = RawOptions { invert: true, pad_rows: false, ..Default::default() };
+        assert_eq!(pack(&img, &unpadded), [0b1100_0000, 0b0000_0000, 0b0001_0000]);
+    }
+
+    #[test]
+    fn packs_indexed_nibbles() {
+        let row = |colors, inks: &[Ink]| {
+            Frame::from_fn(inks.len() as u32, 1, colors, |x, _| inks[x as usize])
+        };
+
+        let acep = row(Colors::Acep, &[Ink::Black, Ink::Orange, Ink::Blue]);
+        assert_eq!(pack_indexed(&acep), [0x06, 0x31]);
+
+        let gray = row(
+            Colors::Grayscale(2),
+            &[Ink::Black, Ink::Gray(85), Ink::Gray(170), Ink::White, Ink::Black],
+        );
+        assert_eq!(pack_indexed(&gray), [0b0001_1011, 0b0011_1111]);
+    }
+}
@@ -0,0 +1,169 @@
+//! When a screen next needs redrawing, so battery-powered panels can sleep until then.
+//!
+//! Each section on screen says when it's next due to change, and the soonest wins:
+//!
+//! - the clock changes at the start of each minute
+//! - a countdown is redrawn once it's out by a quarter, so "40 min" is left for ten minutes but
+//!   "3 min" for one, and a departure time once it's close enough to become a countdown
+//! - the forecast moves on each hour, or when the sun rises or sets if that's sooner
+//! - the calendar moves on to the next day at midnight
+//! - entities can change at any time, so they're checked every few minutes
+//!
+//! Nothing waits more than an hour, as the weather and timetables change without warning too.
+//!
+//! Servers turn that into a hint for devices with [`hint`], so they agree on when to wake them.
+
+use chrono::{DateTime, Duration, NaiveTime, Timelike, Utc};
+use chrono_tz::Tz;
+
+use crate::screen::{local_time, ArrivalTime, HomeSvgTemplate, Icon, COUNTDOWN_MINUTES};
+
+const MAX_WAIT: Duration = Duration::hours(1);
+const ENTITIES_WAIT: Duration = Duration::minutes(5);
+
+/// Added to hints, so a render that's due has finished by the time a device wakes.
+pub const GRACE: Duration = Duration::seconds(5);
+
+fn start_of_hour(time: DateTime<Tz>) -> DateTime<Tz> {
+    time.with_minute(0).unwrap().with_second(0).unwrap().with_nanosecond(0).unwrap()
+}
+
+fn start_of_minute(time: DateTime<Tz>) -> DateTime<Tz> {
+    time.with_second(0).unwrap().with_nanosecond(0).unwrap()
+}
+
+fn next_midnight(time: DateTime<Tz>) -> DateTime<Tz> {
+    let midnight = (time.date_naive() + Duration::days(1)).and_time(NaiveTime::MIN);
+    local_time(&time.timezone(), midnight)
+}
+
+/// When the countdown to a departure next needs redrawing.
+fn next_countdown_change(time: &ArrivalTime, now: DateTime<Tz>) -> DateTime<Tz> {
+    match time {
+        ArrivalTime::Now => now + Duration::minutes(1),
+        ArrivalTime::Minutes(minutes) => now + Duration::minutes(i64::from(*minutes / 4).max(1)),
+        ArrivalTime::Time(time) => *time - Duration::minutes(COUNTDOWN_MINUTES),
+    }
+}
+
+/// When the screen drawn from `model` next changes. `shows_clock` is false for widget layouts
+/// without a clock.
+pub(crate) fn next_refresh(model: &HomeSvgTemplate, shows_clock: bool) -> DateTime<Tz> {
+    let now = model.time;
+    let mut changes = vec![now + MAX_WAIT];
+
+    if shows_clock {
+        changes.push(start_of_minute(now) + Duration::minutes(1));
+    }
+    if let Some(weather) = &model.weather {
+        changes.push(start_of_hour(now) + Duration::hours(1));
+        changes.extend(
+            weather
+                .forecast
+                .iter()
+                .filter(|data| matches!(data.weather, Icon::Sunrise | Icon::Sunset) && data.time > now)
+                .map(|data| data.time),
+        );
+    }
+    if let Some(arrivals) = &model.arrivals {
+        changes.extend(
+            arrivals
+                .arrivals
+                .iter()
+                .flat_map(|data| &data.arrival_times)
+                .map(|time| next_countdown_change(time, now)),
+        );
+    }
+    if model.calendar.is_some() {
+        changes.push(next_midnight(now));
+    }
+    if model.entities.is_some() {
+        changes.push(now + ENTITIES_WAIT);
+    }
+
+    // Anything already due is redrawn as soon as possible
+    changes.into_iter().min().unwrap().max(now)
+}
+
+/// When a device should next ask for a frame that changes at `next_refresh`, and how many seconds
+/// after `now` that is. Only a frame that's failing to update has a hint that's passed, so that's
+/// moved to `back_off` from now instead of having devices wake again straight away.
+pub fn hint(next_refresh: DateTime<Tz>, now: DateTime<Utc>, back_off: Duration) -> (DateTime<Tz>, i64) {
+    let mut next_refresh = next_refresh + GRACE;
+    if next_refresh <= now {
+        next_refresh = now.with_timezone(&next_refresh.timezone()) + back_off;
+    }
+    let refresh_after = (next_refresh.with_timezone(&Utc) - now).num_seconds().max(1);
+    (next_refresh, refresh_after)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::screen::{ArrivalData, ArrivalsSection, WeatherData, WeatherSection};
+
+    fn model(time: DateTime<Tz>) -> HomeSvgTemplate {
+        HomeSvgTemplate {
+            accent: "black".into(),
+            time,
+            weather: None,
+            arrivals: None,
+            calendar: None,
+            entities: None,
+        }
+    }
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Tz> {
+        chrono_tz::Europe::London.with_ymd_and_hms(2024, 6, 1, hour, minute, second).unwrap()
+    }
+
+    #[test]
+    fn waits_for_the_clock_to_change() {
+        assert_eq!(next_refresh(&model(at(12, 0, 30)), true), at(12, 1, 0));
+        assert_eq!(next_refresh(&model(at(12, 0, 30)), false), at(13, 0, 30));
+    }
+
+    #[test]
+    fn waits_for_the_soonest_section_to_change() {
+        let now = at(20, 30, 0);
+        let mut model = model(now);
+        model.weather = Some(WeatherSection {
+            weather_now: Icon::Sun,
+            temp_now: "18".into(),
+            forecast: vec![WeatherData {
+                time: at(21, 10, 0),
+                weather: Icon::Sunset,
+                temp: None,
+            }],
+            as_of: None,
+        });
+        assert_eq!(next_refresh(&model, false), at(21, 0, 0));
+
+        model.weather.as_mut().unwrap().forecast[0].time = at(20, 50, 0);
+        assert_eq!(next_refresh(&model, false), at(20, 50, 0));
+
+        model.arrivals = Some(ArrivalsSection {
+            arrivals: vec![ArrivalData {
+                route: "25".into(),
+                headsign: "Oxford Circus".into(),
+                arrival_times: vec![ArrivalTime::Minutes(40), ArrivalTime::Time(at(23, 0, 0))],
+            }],
+            as_of: None,
+        });
+        assert_eq!(next_refresh(&model, false), at(20, 40, 0));
+
+        model.arrivals.as_mut().unwrap().arrivals[0].arrival_times[0] = ArrivalTime::Minutes(3);
+        assert_eq!(next_refresh(&model, false), at(20, 31, 0));
+    }
+
+    #[test]
+    fn backs_off_when_the_hint_has_passed() {
+        let now = at(12, 0, 0).with_timezone(&Utc);
+        let minute = Duration::minutes(1);
+
+        assert_eq!(hint(at(12, 0, 30), now, minute), (at(12, 0, 35), 35));
+        assert_eq!(hint(at(11, 0, 0), now, minute), (at(12, 1, 0), 60));
+    }
+}
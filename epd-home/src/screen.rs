@@ -1,39 +1,104 @@
 use core::fmt;
-use std::{fmt::Display, io::Write, path::Path, sync::Arc};
+use std::{fmt::Display, path::Path, sync::Arc};
 
 use askama::Template;
-use chrono::{DateTime, NaiveDateTime, TimeZone, Timelike, Utc};
+use chrono::{DateTime, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
 use chrono_tz::Tz;
 use fontdb::Source;
 use itertools::Itertools;
-use serde::Serialize;
-use tiny_skia::{Color, Pixmap};
+use serde::{Deserialize, Serialize};
+use tiny_skia::{Color, FillRule, Mask, PathBuilder, Pixmap};
 use tokio::join;
 use usvg::{ImageHrefResolver, ImageKind};
-use crate::dither::{ditherer::STUCKI, prelude::*};
-
-use crate::{transport::get_stop_arrivals, weather::fetch_weather};
+use crate::dither::{color::palette, ditherer::STUCKI, prelude::*, selective::Selective};
+
+use crate::{
+    cache::{Fetched, SourceCache},
+    calendar::{CalendarSource, Event},
+    frame::{Frame, Rotation},
+    home_assistant::EntitySource,
+    ink::{Colors, Ink},
+    layout::Layout,
+    night::{NightSvgTemplate, QuietHours},
+    transport::{ArrivalsSource, NextAt, RouteArrivals},
+    weather::{OpenMeteo, Weather, WeatherSource},
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Invalid timezone")]
     InvalidTimezone,
 
+    #[error("Unknown dither: {0}")]
+    UnknownDither(String),
+
+    #[error("Rotation must be 0, 90, 180 or 270, not {0}")]
+    InvalidRotation(String),
+
+    #[error("Panel size must be 1 to {MAX_PANEL_SIZE} pixels each way, not {0} x {1}")]
+    InvalidSize(u32, u32),
+
+    #[error("Invalid calendar: {0}")]
+    InvalidCalendar(String),
+
+    #[error("Invalid layout: {0}")]
+    InvalidLayout(String),
+
+    #[error("Unknown panel colours: {0}")]
+    UnknownColors(String),
+
+    #[error("{0} is only supported for black and white frames")]
+    BlackWhiteOnly(&'static str),
+
+    #[error("Failed to encode BMP: {0}")]
+    Bmp(#[from] bmp_monochrome::BmpError),
+
     #[error("Failed to fetch: {0}")]
     Http(#[from] reqwest::Error),
 
     #[error("Missing data: {0}")]
     MissingData(String),
 
+    #[error("Invalid SVG: {0}")]
+    InvalidSvg(#[from] usvg::Error),
+
+    #[error("Failed to render SVG: {0}")]
+    Render(#[from] askama::Error),
+
+    #[error("Timed out after {0:?}")]
+    Timeout(std::time::Duration),
+
     #[error(transparent)]
     InvalidDateFormat(#[from] chrono::ParseError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "gtfs")]
+    #[error("Invalid GTFS archive: {0}")]
+    GtfsArchive(#[from] zip::result::ZipError),
+
+    #[cfg(feature = "gtfs")]
+    #[error("Invalid GTFS data: {0}")]
+    GtfsData(#[from] csv::Error),
+
+    #[cfg(feature = "gtfs")]
+    #[error("Invalid GTFS-Realtime feed: {0}")]
+    GtfsRealtime(#[from] prost::DecodeError),
+
+    #[cfg(feature = "templates")]
+    #[error("Invalid template: {0}")]
+    Template(#[from] minijinja::Error),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-#[derive(Serialize, Clone, PartialEq)]
+/// The largest panel width or height, well beyond any made, so a size can't ask for gigabytes.
+pub const MAX_PANEL_SIZE: u32 = 4096;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "kebab-case")]
-enum Icon {
+pub(crate) enum Icon {
     Sun,
     Cloud,
     CloudDrizzle,
@@ -46,6 +111,13 @@ enum Icon {
     Wind,
 }
 
+impl Icon {
+    /// Severe weather, drawn in the accent colour.
+    pub(crate) fn accent(&self) -> bool {
+        matches!(self, Icon::CloudLightning | Icon::Wind)
+    }
+}
+
 impl Display for Icon {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         serde_json::to_value(self).unwrap().as_str().unwrap().fmt(f)
@@ -79,15 +151,19 @@ fn icon_for_weather(code: u32, is_night: bool, gust_speed: f64) -> Icon {
     }
 }
 
-#[derive(Clone)]
-struct WeatherData {
-    time: DateTime<Tz>,
-    weather: Icon,
-    temp: Option<String>,
+#[derive(Serialize, Clone)]
+pub(crate) struct WeatherData {
+    pub(crate) time: DateTime<Tz>,
+    pub(crate) weather: Icon,
+    pub(crate) temp: Option<String>,
 }
 
-#[derive(Debug)]
-enum ArrivalTime {
+/// Departures sooner than this are shown as a countdown, later ones as a time.
+pub(crate) const COUNTDOWN_MINUTES: i64 = 100;
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", content = "value", rename_all = "lowercase")]
+pub(crate) enum ArrivalTime {
     Now,
     Minutes(u32),
     Time(DateTime<Tz>),
@@ -106,14 +182,14 @@ impl ArrivalTime {
     }
 }
 
-#[derive(Debug)]
-struct ArrivalData {
-    route: String,
-    headsign: String,
-    arrival_times: Vec<ArrivalTime>,
+#[derive(Serialize, Debug)]
+pub(crate) struct ArrivalData {
+    pub(crate) route: String,
+    pub(crate) headsign: String,
+    pub(crate) arrival_times: Vec<ArrivalTime>,
 }
 
-mod filters {
+pub(crate) mod filters {
     use chrono::DateTime;
     use chrono_tz::Tz;
     use titlecase::titlecase as title_case;
@@ -127,18 +203,76 @@ mod filters {
     }
 }
 
-#[derive(Template)]
+/// `as_of` is set when the section is showing cached data because the source failed.
+#[derive(Serialize)]
+pub(crate) struct WeatherSection {
+    pub(crate) weather_now: Icon,
+    pub(crate) temp_now: String,
+    pub(crate) forecast: Vec<WeatherData>,
+    pub(crate) as_of: Option<DateTime<Tz>>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ArrivalsSection {
+    pub(crate) arrivals: Vec<ArrivalData>,
+    pub(crate) as_of: Option<DateTime<Tz>>,
+}
+
+/// Today's and tomorrow's events.
+#[derive(Serialize)]
+pub(crate) struct CalendarSection {
+    pub(crate) days: Vec<CalendarDay>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CalendarDay {
+    /// "today" or "tomorrow"
+    pub(crate) label: String,
+    pub(crate) date: DateTime<Tz>,
+    pub(crate) events: Vec<CalendarEvent>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CalendarEvent {
+    pub(crate) summary: String,
+    /// `None` for all-day events, and those carrying on from the day before.
+    pub(crate) time: Option<DateTime<Tz>>,
+}
+
+/// The screen's Home Assistant entities, in the order they were configured.
+#[derive(Serialize)]
+pub(crate) struct EntitiesSection {
+    pub(crate) entities: Vec<EntityData>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct EntityData {
+    pub(crate) entity_id: String,
+    /// The friendly name, or the entity ID if there isn't one
+    pub(crate) name: String,
+    pub(crate) state: String,
+    pub(crate) unit: Option<String>,
+}
+
+/// Each section is `None` if its data couldn't be gathered, so the rest of the screen can still render.
+/// Runtime templates get this serialized, see [`crate::template`].
+#[derive(Template, Serialize)]
 #[template(path = "home.svg")]
-struct HomeSvgTemplate {
-    weather_now: Icon,
-    temp_now: String,
-    time: DateTime<Tz>,
-    forecast: Vec<WeatherData>,
-    arrivals: Vec<ArrivalData>,
+pub(crate) struct HomeSvgTemplate {
+    /// CSS colour for elements marked with the `accent` class, black unless the screen has an accent.
+    pub(crate) accent: String,
+    pub(crate) time: DateTime<Tz>,
+    pub(crate) weather: Option<WeatherSection>,
+    pub(crate) arrivals: Option<ArrivalsSection>,
+    /// Only gathered for screens with calendars, and not shown by the built-in layout.
+    pub(crate) calendar: Option<CalendarSection>,
+    /// Only gathered for screens with entities, and not shown by the built-in layout.
+    pub(crate) entities: Option<EntitiesSection>,
 }
 
 
-fn save_to_image_bytes(pixmap: Pixmap) -> Vec<Vec<bool>> {
+/// Only the pixels set in `diffuse` are dithered, the rest are the nearest colour the panel has.
+fn save_to_image_bytes(pixmap: Pixmap, ditherer: &Method<'_>, colors: Colors, diffuse: &[bool]) -> Frame {
     // https://gitlab.com/efronlicht/dither/-/blob/master/src/bin/dither.rs?ref_type=heads
 
     let width = pixmap.width();
@@ -146,26 +280,48 @@ fn save_to_image_bytes(pixmap: Pixmap) -> Vec<Vec<bool>> {
         RGB(p.red(), p.green(), p.blue())
     });
 
-    let img: Img<f64> = Img::<RGB<u8>>::new(pixels, width).unwrap()
-        .convert_with(|rgb| rgb.convert_with(f64::from))
-        .convert_with(|rgb| rgb.to_chroma_corrected_black_and_white());
+    let img = Img::<RGB<u8>>::new(pixels, width).unwrap()
+        .convert_with(|rgb| rgb.convert_with(f64::from));
 
-    let quantize = create_quantize_n_bits_func(1).unwrap();
-
-    let output = STUCKI.dither(img, quantize);
+    // Ordered dithering offsets pixels by up to about the gap between the levels, which for
+    // palettes is roughly the distance between neighbouring colours in the RGB cube
+    let spread = match colors {
+        Colors::BlackWhite => 255.,
+        Colors::Grayscale(bits) => 255. / f64::from((1u8 << bits) - 1),
+        _ => 255. / (colors.inks().len() as f64).cbrt(),
+    };
+    let ditherer = Selective::new(ditherer.clone().with_spread(spread), diffuse);
+
+    let output: Img<Ink> = match colors {
+        Colors::BlackWhite => {
+            let img = img.convert_with(|rgb| rgb.to_chroma_corrected_black_and_white());
+            let quantize = create_quantize_n_bits_func(1).unwrap();
+            ditherer
+                .dither(img, quantize)
+                .convert_with(|p| if p == 0.0 { Ink::Black } else { Ink::White })
+        }
+        Colors::Grayscale(bits) => {
+            let img = img.convert_with(|rgb| rgb.to_chroma_corrected_black_and_white());
+            let quantize = create_quantize_n_bits_func(bits).unwrap();
+            ditherer.dither(img, quantize).convert_with(|p| match p as u8 {
+                0 => Ink::Black,
+                255 => Ink::White,
+                level => Ink::Gray(level),
+            })
+        }
+        _ => {
+            let inks = colors.palette();
+            let palette = inks.iter().map(|(_, (r, g, b))| RGB(*r, *g, *b)).collect_vec();
+            ditherer.dither(img, palette::quantize(&palette)).convert_with(|p| {
+                let rgb = p.convert_with(|c| c as u8);
+                inks.iter()
+                    .find(|(_, (r, g, b))| rgb == RGB(*r, *g, *b))
+                    .map_or(Ink::White, |(ink, _)| *ink)
+            })
+        }
+    };
 
-    let data = (0..pixmap.height())
-        .map(|y| {
-            (0..pixmap.width())
-                .map(|x| {
-                    let p = output.get((x, y)).unwrap();
-                    *p == 0.0
-                })
-                .collect_vec()
-        })
-        .collect_vec();
-    
-    data
+    Frame::from_fn(pixmap.width(), pixmap.height(), colors, |x, y| *output.get((x, y)).unwrap())
 }
 
 fn load_icon(icon_name: &str) -> Option<Vec<u8>> {
@@ -185,7 +341,14 @@ fn load_icon(icon_name: &str) -> Option<Vec<u8>> {
     }
 }
 
-async fn render_svg(svg_data: Vec<u8>) -> Vec<Vec<bool>> {
+/// Icons under `icons/accent/` are drawn in the accent colour. If `size` is given, the SVG is
+/// scaled to fit it and centred.
+async fn render_svg(
+    svg_data: Vec<u8>,
+    ditherer: &Method<'_>,
+    colors: Colors,
+    size: Option<(u32, u32)>,
+) -> Result<Frame> {
     // Based on https://github.com/RazrFalcon/resvg/blob/master/crates/resvg/examples/minimal.rs
 
     log::debug!("Make SVG tree");
@@ -201,9 +364,23 @@ async fn render_svg(svg_data: Vec<u8>) -> Vec<Vec<bool>> {
             resources_dir: Some(dir),
             image_href_resolver: ImageHrefResolver {
                 resolve_string: Box::new(move |href, opts, fontdb| {
+                    // Templates choose the href, so one that doesn't load is left out rather than
+                    // failing the whole screen
+                    let parse = |icon: &[u8]| {
+                        usvg::Tree::from_data(icon, opts, fontdb)
+                            .inspect_err(|err| log::warn!("Failed to load {}: {}", href, err))
+                            .ok()
+                            .map(ImageKind::SVG)
+                    };
+                    if let Some(name) = href.strip_prefix("icons/accent/") {
+                        if let Some(icon) = load_icon(name) {
+                            let icon = String::from_utf8_lossy(&icon).replace("currentColor", &accent_css(colors));
+                            return parse(icon.as_bytes());
+                        }
+                    }
                     if let Some(name) = href.strip_prefix("icons/") {
                         if let Some(icon) = load_icon(name) {
-                            return Some(ImageKind::SVG(usvg::Tree::from_data(&icon, opts, fontdb).unwrap()));
+                            return parse(&icon);
                         }
                     }
                     None
@@ -212,17 +389,64 @@ async fn render_svg(svg_data: Vec<u8>) -> Vec<Vec<bool>> {
             },
             ..Default::default()
         };
-        usvg::Tree::from_data(&svg_data, &opt, &fontdb).unwrap()
+        usvg::Tree::from_data(&svg_data, &opt, &fontdb)?
     };
 
     log::debug!("Render SVG");
 
-    let pixmap_size = tree.size().to_int_size();
-    let mut pixmap = tiny_skia::Pixmap::new(pixmap_size.width(), pixmap_size.height()).unwrap();
+    let tree_size = tree.size();
+    let (width, height) = size.unwrap_or_else(|| {
+        let size = tree_size.to_int_size();
+        (size.width(), size.height())
+    });
+    let scale = (width as f32 / tree_size.width()).min(height as f32 / tree_size.height());
+    let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(
+        (width as f32 - tree_size.width() * scale) / 2.,
+        (height as f32 - tree_size.height() * scale) / 2.,
+    );
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).unwrap();
     pixmap.fill(Color::WHITE);
-    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+
+    let mut mask = Mask::new(pixmap.width(), pixmap.height()).unwrap();
+    mark_diffused(tree.root(), transform, &mut mask);
+    let diffuse = mask.data().iter().map(|coverage| *coverage > 0).collect_vec();
 
-    save_to_image_bytes(pixmap)
+    Ok(save_to_image_bytes(pixmap, ditherer, colors, &diffuse))
+}
+
+/// Marks the areas to dither: raster images, anything filled or stroked with a gradient or
+/// pattern, and `<g>` elements with an id starting with `dither`. Text, icons and flat shapes
+/// are left out, since dithering their anti-aliased edges leaves speckles around them.
+fn mark_diffused(group: &usvg::Group, transform: tiny_skia::Transform, mask: &mut Mask) {
+    for node in group.children() {
+        let rect = match node {
+            usvg::Node::Group(group) if group.id().starts_with("dither") => {
+                group.abs_layer_bounding_box().to_rect()
+            }
+            usvg::Node::Group(group) => {
+                mark_diffused(group, transform, mask);
+                continue;
+            }
+            usvg::Node::Image(image) if !matches!(image.kind(), ImageKind::SVG(_)) => image.abs_bounding_box(),
+            usvg::Node::Path(path) => {
+                let paints = [path.fill().map(|fill| fill.paint()), path.stroke().map(|stroke| stroke.paint())];
+                if !paints.into_iter().flatten().any(|paint| !matches!(paint, usvg::Paint::Color(_))) {
+                    continue;
+                }
+                node.abs_stroke_bounding_box()
+            }
+            _ => continue,
+        };
+        let path = PathBuilder::from_rect(rect);
+        mask.fill_path(&path, FillRule::Winding, false, transform);
+    }
+}
+
+fn accent_css(colors: Colors) -> String {
+    let (r, g, b) = colors.accent().rgb();
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
 }
 
 fn parse_weather_time(time: &str, tz: &Tz) -> Result<DateTime<Tz>, chrono::ParseError> {
@@ -231,11 +455,48 @@ fn parse_weather_time(time: &str, tz: &Tz) -> Result<DateTime<Tz>, chrono::Parse
     Ok(dt)
 }
 
+/// What's on the screen, gathered but not yet drawn.
+enum Content {
+    Home(HomeSvgTemplate),
+    Night(NightSvgTemplate),
+}
+
+/// A rendered screen, and when it's next due to change, see [`crate::refresh`].
+#[derive(Clone)]
+pub struct Rendered {
+    pub frame: Frame,
+    pub next_refresh: DateTime<Tz>,
+}
+
+/// A wall-clock time in `tz`, or an hour later if the clocks skipped it.
+pub(crate) fn local_time(tz: &Tz, local: NaiveDateTime) -> DateTime<Tz> {
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| tz.from_local_datetime(&(local + chrono::Duration::hours(1))).earliest())
+        .unwrap_or_else(|| tz.from_utc_datetime(&local))
+}
+
 pub struct Screen {
     latitude: f64,
     longitude: f64,
     timezone: Tz,
     stop_codes: Vec<String>,
+    weather_source: Arc<dyn WeatherSource>,
+    arrivals_source: Arc<dyn ArrivalsSource>,
+    calendars: Vec<Arc<dyn CalendarSource>>,
+    entity_source: Option<Arc<dyn EntitySource>>,
+    entity_ids: Vec<String>,
+    cache: Option<Arc<SourceCache>>,
+    ditherer: Method<'static>,
+    serpentine: bool,
+    linear_light: bool,
+    colors: Colors,
+    size: Option<(u32, u32)>,
+    rotation: Rotation,
+    layout: Option<Layout>,
+    quiet_hours: Option<QuietHours>,
+    #[cfg(feature = "templates")]
+    template: Option<std::path::PathBuf>,
 }
 
 impl Screen {
@@ -247,28 +508,208 @@ impl Screen {
             longitude,
             timezone: tz,
             stop_codes: stop_codes.iter().map(|s| s.to_string()).collect(),
+            weather_source: Arc::new(OpenMeteo),
+            arrivals_source: Arc::new(NextAt),
+            calendars: vec![],
+            entity_source: None,
+            entity_ids: vec![],
+            cache: None,
+            ditherer: STUCKI.into(),
+            serpentine: false,
+            linear_light: false,
+            size: None,
+            rotation: Rotation::None,
+            layout: None,
+            quiet_hours: None,
+            #[cfg(feature = "templates")]
+            template: None,
+            colors: Colors::BlackWhite,
         };
         Ok(screen)
     }
 
+    /// Use a different weather provider instead of Open-Meteo.
+    pub fn with_weather_source(mut self, source: Arc<dyn WeatherSource>) -> Self {
+        self.weather_source = source;
+        self
+    }
+
+    /// Use a different arrivals provider instead of next-at-api.
+    pub fn with_arrivals_source(mut self, source: Arc<dyn ArrivalsSource>) -> Self {
+        self.arrivals_source = source;
+        self
+    }
+
+    /// Show events from a calendar, e.g. an [`Ics`](crate::calendar::Ics) feed, alongside any
+    /// added before.
+    pub fn with_calendar(mut self, source: Arc<dyn CalendarSource>) -> Self {
+        self.calendars.push(source);
+        self
+    }
+
+    /// Show the states of Home Assistant entities, e.g. `sensor.lounge_temperature`, from
+    /// [`HomeAssistant`](crate::home_assistant::HomeAssistant) or another source.
+    pub fn with_entities(mut self, source: Arc<dyn EntitySource>, entity_ids: &[&str]) -> Self {
+        self.entity_source = Some(source);
+        self.entity_ids = entity_ids.iter().map(|id| id.to_string()).collect();
+        self
+    }
+
+    /// Serve data from a cache shared with other screens, falling back to the last good data
+    /// when a source fails.
+    pub fn with_cache(mut self, cache: Arc<SourceCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Use a different error diffusion kernel instead of Stucki, e.g. `floyd`, `atkinson` or
+    /// `sierra3`, or ordered dithering with `bayer2`, `bayer4`, `bayer8` or `blue-noise`. Ordered
+    /// dithering draws unchanged areas the same every frame, so suits partial refresh.
+    pub fn with_dither(mut self, name: &str) -> Result<Self> {
+        self.ditherer = name
+            .parse()
+            .map_err(|_| Error::UnknownDither(name.to_string()))?;
+        Ok(self)
+    }
+
+    /// Render for a colour panel, with e.g. "Now" arrivals and severe weather in its accent colour.
+    pub fn with_colors(mut self, colors: Colors) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    /// Scan alternate rows in opposite directions when diffusing error, which avoids diagonal
+    /// streaks in photos and gradients.
+    pub fn with_serpentine(mut self, serpentine: bool) -> Self {
+        self.serpentine = serpentine;
+        self
+    }
+
+    /// Diffuse error in linear light rather than sRGB, so dithered midtones keep their
+    /// brightness instead of coming out dark.
+    pub fn with_linear_light(mut self, linear_light: bool) -> Self {
+        self.linear_light = linear_light;
+        self
+    }
+
+    /// Render for a panel of `width` x `height` pixels instead of 800 x 480. The layout is
+    /// scaled to fit, keeping its proportions.
+    pub fn with_size(mut self, width: u32, height: u32) -> Result<Self> {
+        let valid = |pixels: u32| (1..=MAX_PANEL_SIZE).contains(&pixels);
+        if !valid(width) || !valid(height) {
+            return Err(Error::InvalidSize(width, height));
+        }
+        self.size = Some((width, height));
+        Ok(self)
+    }
+
+    /// Rotate the layout clockwise onto the panel, e.g. by 90 degrees for a landscape panel
+    /// mounted in portrait. The size is still the panel's own, before rotation.
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Lay the screen out with a MiniJinja template read from `path` at each render, instead of
+    /// the built-in layout. See [`crate::template`] for what it's given.
+    #[cfg(feature = "templates")]
+    pub fn with_template(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.template = Some(path.into());
+        self
+    }
+
+    /// Compose the screen from widgets on a grid instead of the built-in layout. This takes
+    /// precedence over a template.
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    /// Show the night frame instead during `quiet_hours`, see [`crate::night`].
+    pub fn with_quiet_hours(mut self, quiet_hours: QuietHours) -> Self {
+        self.quiet_hours = Some(quiet_hours);
+        self
+    }
+
+    /// Which of weather, arrivals, calendar and entities the screen shows. A widget layout only
+    /// fetches what its widgets show.
+    fn sections(&self) -> (bool, bool, bool, bool) {
+        match &self.layout {
+            Some(layout) => (
+                layout.needs_weather(),
+                layout.needs_arrivals(),
+                layout.needs_calendar(),
+                layout.needs_entities(),
+            ),
+            None => (true, true, !self.calendars.is_empty(), !self.entity_ids.is_empty()),
+        }
+    }
+
+    /// The SVG for the screen, from the widget layout or template if there is one.
+    fn svg(&self, model: HomeSvgTemplate) -> Result<Vec<u8>> {
+        if let Some(layout) = &self.layout {
+            let canvas = self.canvas().unwrap_or((800, 480));
+            return Ok(crate::layout::render(layout, &model, canvas)?.into());
+        }
+        #[cfg(feature = "templates")]
+        if let Some(path) = &self.template {
+            return Ok(crate::template::render(path, &model)?.into());
+        }
+        Ok(model.render().unwrap().into())
+    }
+
+    /// Renders the SVG at the panel's size and orientation.
+    async fn draw(&self, svg_data: Vec<u8>) -> Result<Frame> {
+        let frame = render_svg(svg_data, &self.ditherer(), self.colors, self.canvas()).await?;
+        Ok(frame.rotate(self.rotation))
+    }
+
+    /// The panel's size as the layout sees it: drawn upright, so on its side for a quarter turn.
+    fn canvas(&self) -> Option<(u32, u32)> {
+        self.size.map(|(width, height)| match self.rotation {
+            Rotation::Quarter | Rotation::ThreeQuarter => (height, width),
+            Rotation::None | Rotation::Half => (width, height),
+        })
+    }
+
+    fn ditherer(&self) -> Method<'static> {
+        self.ditherer
+            .clone()
+            .with_serpentine(self.serpentine)
+            .with_linear_light(self.linear_light)
+    }
+
     fn parse_weather_time(&self, time: &str) -> Result<DateTime<Tz>, chrono::ParseError> {
         parse_weather_time(time, &self.timezone)
     }
 
-    async fn gather_weather(&self) -> Result<(WeatherData, Vec<WeatherData>)> {
-        let weather = fetch_weather(self.latitude, self.longitude).await?;
+    async fn fetch_weather(&self) -> Result<Fetched<Weather>> {
+        let fetch = self
+            .weather_source
+            .fetch_weather(self.latitude, self.longitude);
 
-        let now = Utc::now().with_timezone(&self.timezone);
+        match &self.cache {
+            Some(cache) => {
+                let source = self.weather_source.cache_key();
+                cache.weather(source, self.latitude, self.longitude, fetch).await
+            }
+            None => Ok(Fetched::fresh(fetch.await?)),
+        }
+    }
 
-        let current = WeatherData {
-            time: now,
-            weather: icon_for_weather(
-                weather.current.weather_code,
-                !weather.current.is_day,
-                weather.current.wind_gusts,
-            ),
-            temp: Some(weather.current.temperature.round().to_string()),
-        };
+    async fn fetch_arrivals(&self, stop_code: &str) -> Result<Fetched<Vec<RouteArrivals>>> {
+        let fetch = self.arrivals_source.stop_arrivals(stop_code);
+
+        match &self.cache {
+            Some(cache) => cache.arrivals(self.arrivals_source.cache_key(), stop_code, fetch).await,
+            None => Ok(Fetched::fresh(fetch.await?)),
+        }
+    }
+
+    async fn gather_weather(&self) -> Result<WeatherSection> {
+        let Fetched { value: weather, stale_since } = self.fetch_weather().await?;
+
+        let now = Utc::now().with_timezone(&self.timezone);
 
         let hourly_data = weather
             .forecast
@@ -345,43 +786,51 @@ impl Screen {
             forecast_start += chrono::Duration::try_hours(FORECAST_HOURS as i64).unwrap();
         }
 
-        Ok((current, forecast))
+        let section = WeatherSection {
+            weather_now: icon_for_weather(
+                weather.current.weather_code,
+                !weather.current.is_day,
+                weather.current.wind_gusts,
+            ),
+            temp_now: weather.current.temperature.round().to_string(),
+            forecast,
+            as_of: stale_since.map(|time| time.with_timezone(&self.timezone)),
+        };
+        Ok(section)
     }
 
-    async fn gather_arrivals(&self) -> Result<Vec<ArrivalData>> {
-        let pending_arrivals = self.stop_codes.iter().map(|code| get_stop_arrivals(code)).collect_vec();
+    async fn gather_arrivals(&self) -> Result<ArrivalsSection> {
+        let pending_arrivals = self
+            .stop_codes
+            .iter()
+            .map(|code| self.fetch_arrivals(code))
+            .collect_vec();
         let arrivals: Vec<_> = futures::future::join_all(pending_arrivals).await.into_iter()
-            .filter_map(|item| item.transpose())
             .try_collect()?;
-        let arrivals = arrivals.into_iter().flatten().collect_vec();
+        let stale_since = arrivals.iter().filter_map(|arr| arr.stale_since).min();
+        let arrivals = arrivals.into_iter().flat_map(|arr| arr.value).collect_vec();
 
         let now = Utc::now().with_timezone(&self.timezone);
 
         let data: Vec<_> = arrivals
             .into_iter()
-            .filter(|arr| !arr.arrivals.is_empty())
             .map(|arr| {
                 let data = ArrivalData {
-                    route: arr.route_trip.route_short_name,
-                    headsign: arr.route_trip.stop_headsign,
+                    route: arr.route,
+                    headsign: arr.headsign,
                     arrival_times: arr
-                        .arrivals
+                        .arrival_times
                         .into_iter()
+                        // cached arrivals may include services that have since left
+                        .filter(|time| stale_since.is_none() || *time >= now)
                         .take(2)
-                        .map(|arr_time| {
-                            let time = arr_time
-                                .updated_arrival_timestamp
-                                .unwrap_or(arr_time.arrival_timestamp);
-                            let dt = Utc
-                                .timestamp_millis_opt(time)
-                                .single()
-                                .unwrap()
-                                .with_timezone(&self.timezone);
+                        .map(|time| {
+                            let dt = time.with_timezone(&self.timezone);
                             let delta = dt - now;
 
                             let arrival_time = match delta {
                                 d if d.num_minutes() < 1 => ArrivalTime::Now,
-                                d if d.num_minutes() < 100 => {
+                                d if d.num_minutes() < COUNTDOWN_MINUTES => {
                                     ArrivalTime::Minutes(d.num_minutes() as u32)
                                 }
                                 _ => ArrivalTime::Time(dt),
@@ -395,6 +844,7 @@ impl Screen {
                 };
                 Ok::<_, Error>(data)
             })
+            .filter_ok(|data| !data.arrival_times.is_empty())
             .try_collect()?;
 
         let data = data
@@ -423,74 +873,604 @@ impl Screen {
             .map(|(_, data)| data)
             .collect::<Vec<_>>();
 
-        Ok(data)
+        let section = ArrivalsSection {
+            arrivals: data,
+            as_of: stale_since.map(|time| time.with_timezone(&self.timezone)),
+        };
+        Ok(section)
     }
 
-    pub async fn render(&self) -> Result<Vec<Vec<bool>>> {
-        let (weather, transport) = join!(self.gather_weather(), self.gather_arrivals());
+    async fn gather_calendar(&self) -> Result<CalendarSection> {
+        let today = Utc::now()
+            .with_timezone(&self.timezone)
+            .date_naive()
+            .and_time(NaiveTime::MIN);
+        let midnight = |days: i64| local_time(&self.timezone, today + chrono::Duration::days(days));
+        let (start, end) = (midnight(0), midnight(2));
 
-        let (current_weather, forecast) = weather?;
-        let arrivals = transport?;
-        log::debug!("{:?}", arrivals);
+        let pending = self
+            .calendars
+            .iter()
+            .map(|source| source.events(start, end))
+            .collect_vec();
+        let events: Vec<Vec<Event>> = futures::future::join_all(pending).await.into_iter().try_collect()?;
+        let events = events.into_iter().flatten().sorted_by_key(|event| (event.start, !event.all_day)).collect_vec();
 
-        let svg_data: Vec<u8> = HomeSvgTemplate {
-            weather_now: current_weather.weather,
-            temp_now: current_weather
-                .temp
-                .map(|temp| temp.to_string())
-                .ok_or_else(|| Error::MissingData("current temperature".into()))?,
-            time: current_weather.time,
-            forecast,
-            arrivals,
-        }
-        .render()
-        .unwrap()
-        .into();
+        const EVENTS_PER_DAY: usize = 4;
+
+        let days = ["today", "tomorrow"]
+            .into_iter()
+            .enumerate()
+            .map(|(n, label)| {
+                let (day_start, day_end) = (midnight(n as i64), midnight(n as i64 + 1));
+                let events = events
+                    .iter()
+                    .filter(|event| event.start < day_end && (event.end > day_start || event.start >= day_start))
+                    .take(EVENTS_PER_DAY)
+                    .map(|event| CalendarEvent {
+                        summary: event.summary.clone(),
+                        time: (!event.all_day && event.start >= day_start).then_some(event.start),
+                    })
+                    .collect();
+                CalendarDay {
+                    label: label.into(),
+                    date: day_start,
+                    events,
+                }
+            })
+            .collect();
+
+        Ok(CalendarSection { days })
+    }
+
+    async fn gather_entities(&self) -> Result<EntitiesSection> {
+        let source = self
+            .entity_source
+            .as_ref()
+            .ok_or_else(|| Error::MissingData("Home Assistant entity source".into()))?;
+        let pending = self
+            .entity_ids
+            .iter()
+            .map(|id| source.entity_state(id))
+            .collect_vec();
+        let states: Vec<_> = futures::future::join_all(pending).await.into_iter().try_collect()?;
+
+        let entities = states
+            .into_iter()
+            .map(|state| EntityData {
+                name: state.name.clone().unwrap_or_else(|| state.entity_id.clone()),
+                state: state.display_state(),
+                unit: state.unit,
+                entity_id: state.entity_id,
+            })
+            .collect();
+        Ok(EntitiesSection { entities })
+    }
+
+    pub async fn render(&self) -> Result<Frame> {
+        Ok(self.render_with_refresh().await?.frame)
+    }
+
+    /// Renders the screen, working out from what's on it when it should next be rendered.
+    pub async fn render_with_refresh(&self) -> Result<Rendered> {
+        let content = self.gather().await;
+        let next_refresh = self.next_refresh_for(&content);
+
+        let svg_data = match content {
+            Content::Home(model) => self.svg(model)?,
+            Content::Night(model) => model.render().unwrap().into(),
+        };
 
         log::debug!("SVG data: {}", String::from_utf8_lossy(&svg_data));
 
-        let img_data = render_svg(svg_data).await;
+        let frame = self.draw(svg_data).await?;
 
-        Ok(img_data)
+        Ok(Rendered { frame, next_refresh })
     }
 
-    pub async fn render_placeholder<T: Write>(&self) -> Result<Vec<Vec<bool>>> {
-        let fake_now = Utc::now().with_timezone(&self.timezone).with_hour(12).unwrap().with_minute(0).unwrap();
+    /// When the screen should next be rendered, without drawing it. This still fetches everything
+    /// on the screen, so use the hint from [`Screen::render_with_refresh`] where there is one.
+    pub async fn next_refresh(&self) -> DateTime<Tz> {
+        let content = self.gather().await;
+        self.next_refresh_for(&content)
+    }
 
-        let svg_data: Vec<u8> = HomeSvgTemplate {
-            weather_now: Icon::Cloud,
-            temp_now: "-".into(),
-            time: fake_now,
-            forecast: (1..=4).map(|n| {
-                WeatherData {
-                    time: fake_now.with_hour(n * 2 + 12).unwrap(),
-                    weather: Icon::Cloud,
-                    temp: Some("-".into())
+    fn next_refresh_for(&self, content: &Content) -> DateTime<Tz> {
+        match content {
+            Content::Home(model) => {
+                let shows_clock = match &self.layout {
+                    Some(layout) => layout.shows_clock(),
+                    None => true,
+                };
+                crate::refresh::next_refresh(model, shows_clock)
+            }
+            Content::Night(model) => model.until,
+        }
+    }
+
+    /// Everything on the screen, or the night frame during quiet hours.
+    async fn gather(&self) -> Content {
+        let now = Utc::now().with_timezone(&self.timezone);
+        match self.quiet_hours.and_then(|quiet_hours| quiet_hours.ends_at(now)) {
+            Some(until) => Content::Night(self.gather_night(until).await),
+            None => Content::Home(self.gather_home().await),
+        }
+    }
+
+    async fn gather_home(&self) -> HomeSvgTemplate {
+        let (needs_weather, needs_arrivals, needs_calendar, needs_entities) = self.sections();
+        let (weather, transport, calendar, entities) = join!(
+            async {
+                match needs_weather {
+                    true => Some(self.gather_weather().await),
+                    false => None,
                 }
-            }).collect_vec(),
-            arrivals: (1..=4).map(|n| {
-                ArrivalData {
-                    route: "---".into(),
-                    headsign: "----------".into(),
-                    arrival_times: vec![ArrivalTime::Minutes(n * 10)]
+            },
+            async {
+                match needs_arrivals {
+                    true => Some(self.gather_arrivals().await),
+                    false => None,
                 }
-            }).collect_vec()
+            },
+            async {
+                match needs_calendar {
+                    true => Some(self.gather_calendar().await),
+                    false => None,
+                }
+            },
+            async {
+                match needs_entities {
+                    true => Some(self.gather_entities().await),
+                    false => None,
+                }
+            },
+        );
+
+        let weather = weather.and_then(|weather| {
+            weather
+                .inspect_err(|err| log::error!("Failed to gather weather: {}", err))
+                .ok()
+        });
+        let arrivals = transport.and_then(|transport| {
+            transport
+                .inspect_err(|err| log::error!("Failed to gather arrivals: {}", err))
+                .ok()
+        });
+        let calendar = calendar.and_then(|calendar| {
+            calendar
+                .inspect_err(|err| log::error!("Failed to gather calendar: {}", err))
+                .ok()
+        });
+        let entities = entities.and_then(|entities| {
+            entities
+                .inspect_err(|err| log::error!("Failed to gather entities: {}", err))
+                .ok()
+        });
+        log::debug!("{:?}", arrivals.as_ref().map(|section| &section.arrivals));
+
+        HomeSvgTemplate {
+            accent: accent_css(self.colors),
+            time: Utc::now().with_timezone(&self.timezone),
+            weather,
+            arrivals,
+            calendar,
+            entities,
+        }
+    }
+
+    /// The night frame, shown until the quiet hours end `until`.
+    async fn gather_night(&self, until: DateTime<Tz>) -> NightSvgTemplate {
+        let (needs_weather, needs_arrivals, _, _) = self.sections();
+        let (forecast, departure) = join!(
+            async {
+                match needs_weather {
+                    true => Some(self.gather_night_forecast(until).await),
+                    false => None,
+                }
+            },
+            async {
+                match needs_arrivals {
+                    true => Some(self.gather_night_departure(until).await),
+                    false => None,
+                }
+            },
+        );
+
+        let forecast = forecast.and_then(|forecast| {
+            forecast
+                .inspect_err(|err| log::error!("Failed to gather forecast: {}", err))
+                .ok()
+                .flatten()
+        });
+        let departure = departure.and_then(|departure| {
+            departure
+                .inspect_err(|err| log::error!("Failed to gather departures: {}", err))
+                .ok()
+                .flatten()
+        });
+
+        NightSvgTemplate {
+            accent: accent_css(self.colors),
+            until,
+            forecast,
+            departure,
+        }
+    }
+
+    /// The hourly forecast covering `time`.
+    async fn gather_night_forecast(&self, time: DateTime<Tz>) -> Result<Option<WeatherData>> {
+        let weather = self.fetch_weather().await?.value;
+
+        let mut forecast = None;
+        for data in &weather.forecast {
+            let start = self.parse_weather_time(&data.time)?;
+            if start <= time {
+                forecast = Some(WeatherData {
+                    time: start,
+                    weather: icon_for_weather(data.weather_code, !data.is_day, data.wind_gusts),
+                    temp: Some(data.temperature.round().to_string()),
+                });
+            }
         }
-        .render()
-        .unwrap()
-        .into();
+        Ok(forecast)
+    }
+
+    /// The first departure from any of the screen's stops from `time`, if it's known yet.
+    async fn gather_night_departure(&self, time: DateTime<Tz>) -> Result<Option<ArrivalData>> {
+        let pending = self
+            .stop_codes
+            .iter()
+            .map(|code| self.fetch_arrivals(code))
+            .collect_vec();
+        let arrivals: Vec<_> = futures::future::join_all(pending).await.into_iter().try_collect()?;
+
+        let departure = arrivals
+            .into_iter()
+            .flat_map(|arrivals| arrivals.value)
+            .filter_map(|arrivals| {
+                let first = arrivals
+                    .arrival_times
+                    .iter()
+                    .map(|arrival| arrival.with_timezone(&self.timezone))
+                    .find(|arrival| *arrival >= time)?;
+                Some((first, arrivals))
+            })
+            .min_by_key(|(first, _)| *first)
+            .map(|(first, arrivals)| ArrivalData {
+                route: arrivals.route,
+                headsign: arrivals.headsign,
+                arrival_times: vec![ArrivalTime::Time(first)],
+            });
+        Ok(departure)
+    }
+
+    pub async fn render_placeholder(&self) -> Result<Frame> {
+        let fake_now = Utc::now().with_timezone(&self.timezone).with_hour(12).unwrap().with_minute(0).unwrap();
 
-        let data = render_svg(svg_data).await;
+        let svg_data = self.svg(HomeSvgTemplate {
+            accent: accent_css(self.colors),
+            time: fake_now,
+            weather: Some(WeatherSection {
+                weather_now: Icon::Cloud,
+                temp_now: "-".into(),
+                forecast: (1..=4).map(|n| {
+                    WeatherData {
+                        time: fake_now.with_hour(n * 2 + 12).unwrap(),
+                        weather: Icon::Cloud,
+                        temp: Some("-".into())
+                    }
+                }).collect_vec(),
+                as_of: None,
+            }),
+            arrivals: Some(ArrivalsSection {
+                arrivals: (1..=4).map(|n| {
+                    ArrivalData {
+                        route: "---".into(),
+                        headsign: "----------".into(),
+                        arrival_times: vec![ArrivalTime::Minutes(n * 10)]
+                    }
+                }).collect_vec(),
+                as_of: None,
+            }),
+            calendar: Some(CalendarSection {
+                days: ["today", "tomorrow"].into_iter().enumerate().map(|(n, label)| {
+                    CalendarDay {
+                        label: label.into(),
+                        date: fake_now + chrono::Duration::days(n as i64),
+                        events: (1..=2).map(|hours| CalendarEvent {
+                            summary: "----------".into(),
+                            time: Some(fake_now + chrono::Duration::hours(hours * 3)),
+                        }).collect_vec(),
+                    }
+                }).collect_vec(),
+            }),
+            entities: Some(EntitiesSection {
+                entities: (1..=3).map(|n| {
+                    EntityData {
+                        entity_id: format!("sensor.placeholder_{}", n),
+                        name: "----------".into(),
+                        state: "-".into(),
+                        unit: None,
+                    }
+                }).collect_vec(),
+            }),
+        })?;
+
+        let data = self.draw(svg_data).await?;
 
         Ok(data)
     }
 
-    pub async fn render_error<T: Write>(&self) -> Result<Vec<Vec<bool>>> {
+    pub async fn render_error(&self) -> Result<Frame> {
         let svg_data: Vec<u8> = include_bytes!("../assets/error.svg").into();
 
-        let data = render_svg(svg_data).await;
+        let data = self.draw(svg_data).await?;
 
         Ok(data)
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use chrono::Duration;
+
+    use super::*;
+    use crate::{ink::Accent, weather::WeatherForecast};
+
+    struct FakeWeather;
+
+    #[async_trait]
+    impl WeatherSource for FakeWeather {
+        async fn fetch_weather(&self, _latitude: f64, _longitude: f64) -> Result<Weather> {
+            let now = Utc::now();
+            let forecast = |hours: i64| WeatherForecast {
+                time: (now + Duration::hours(hours)).format("%Y-%m-%dT%H:00").to_string(),
+                weather_code: 61,
+                temperature: 14.6,
+                is_day: true,
+                wind_gusts: 10.0,
+            };
+            Ok(Weather {
+                current: forecast(0),
+                forecast: (0..24).map(forecast).collect(),
+                sunrises: vec![],
+                sunsets: vec![(now + Duration::hours(3)).format("%Y-%m-%dT%H:%M").to_string()],
+            })
+        }
+    }
+
+    struct FakeArrivals;
+
+    #[async_trait]
+    impl ArrivalsSource for FakeArrivals {
+        async fn stop_arrivals(&self, stop_code: &str) -> Result<Vec<RouteArrivals>> {
+            let now = Utc::now();
+            Ok(vec![RouteArrivals {
+                route: stop_code.into(),
+                headsign: "BRITOMART".into(),
+                arrival_times: vec![now + Duration::minutes(4), now + Duration::minutes(19)],
+            }])
+        }
+    }
+
+    struct ArrivingNow;
+
+    #[async_trait]
+    impl ArrivalsSource for ArrivingNow {
+        async fn stop_arrivals(&self, stop_code: &str) -> Result<Vec<RouteArrivals>> {
+            Ok(vec![RouteArrivals {
+                route: stop_code.into(),
+                headsign: "BRITOMART".into(),
+                arrival_times: vec![Utc::now() + Duration::seconds(30)],
+            }])
+        }
+    }
+
+    struct FailingArrivals;
+
+    #[async_trait]
+    impl ArrivalsSource for FailingArrivals {
+        async fn stop_arrivals(&self, _stop_code: &str) -> Result<Vec<RouteArrivals>> {
+            Err(Error::MissingData("arrivals".into()))
+        }
+    }
+
+    #[tokio::test]
+    async fn renders_with_custom_sources() {
+        let img = Screen::new(-36.85, 174.76, "Pacific/Auckland", &["NX1", "70"])
+            .unwrap()
+            .with_weather_source(Arc::new(FakeWeather))
+            .with_arrivals_source(Arc::new(FakeArrivals))
+            .render()
+            .await
+            .unwrap();
+
+        assert_eq!((img.width(), img.height()), (800, 480));
+        assert!(img.pixels().any(|px| px == Ink::Black));
+    }
+
+    #[tokio::test]
+    async fn renders_night_frame_during_quiet_hours() {
+        let screen = || {
+            Screen::new(-36.85, 174.76, "Pacific/Auckland", &["NX1"])
+                .unwrap()
+                .with_weather_source(Arc::new(FakeWeather))
+                .with_arrivals_source(Arc::new(FakeArrivals))
+        };
+        let now = Utc::now().with_timezone(&chrono_tz::Pacific::Auckland);
+        let quiet_hours = QuietHours::new((now - Duration::hours(1)).time(), (now + Duration::hours(1)).time());
+
+        let night = screen().with_quiet_hours(quiet_hours).render_with_refresh().await.unwrap();
+        let day = screen().render().await.unwrap();
+
+        assert_eq!(Some(night.next_refresh), quiet_hours.ends_at(now));
+        assert!(night.frame.pixels().any(|px| px == Ink::Black));
+        assert!(night.frame.pixels().zip(day.pixels()).any(|(night, day)| night != day));
+    }
+
+    #[tokio::test]
+    async fn renders_accent_colour() {
+        let img = Screen::new(-36.85, 174.76, "Pacific/Auckland", &["NX1"])
+            .unwrap()
+            .with_weather_source(Arc::new(FakeWeather))
+            .with_arrivals_source(Arc::new(ArrivingNow))
+            .with_colors(Colors::ThreeColor(Accent::Red))
+            .render()
+            .await
+            .unwrap();
+
+        assert!(img.pixels().any(|px| px == Ink::Black));
+        assert!(img.pixels().any(|px| px == Ink::Red));
+    }
+
+    #[tokio::test]
+    async fn renders_grayscale() {
+        let img = Screen::new(-36.85, 174.76, "Pacific/Auckland", &[])
+            .unwrap()
+            .with_colors(Colors::Grayscale(2))
+            .render_placeholder()
+            .await
+            .unwrap();
+
+        assert!(img.pixels().any(|px| px == Ink::Gray(85)));
+        assert!(img.pixels().any(|px| px == Ink::Gray(170)));
+    }
+
+    #[tokio::test]
+    async fn renders_at_panel_size() {
+        let screen = Screen::new(-36.85, 174.76, "Pacific/Auckland", &[]).unwrap();
+        let img = screen.with_size(400, 300).unwrap().render_placeholder().await.unwrap();
+        assert_eq!((img.width(), img.height()), (400, 300));
+
+        // A portrait-mounted 7.5" panel, with the layout on its side in the panel's frame
+        let screen = Screen::new(-36.85, 174.76, "Pacific/Auckland", &[]).unwrap();
+        let img = screen
+            .with_size(800, 480)
+            .unwrap()
+            .with_rotation("90".parse().unwrap())
+            .render_placeholder()
+            .await
+            .unwrap();
+        assert_eq!((img.width(), img.height()), (800, 480));
+        // Scaled to 480 wide, so there's a margin above and below the layout, which is now
+        // on the left and right
+        assert!((0..480).all(|y| img.get(0, y) == Some(Ink::White)));
+        assert!("45".parse::<Rotation>().is_err());
+
+        let screen = || Screen::new(-36.85, 174.76, "Pacific/Auckland", &[]).unwrap();
+        assert!(matches!(screen().with_size(0, 480), Err(Error::InvalidSize(0, 480))));
+        assert!(matches!(screen().with_size(800, 60000), Err(Error::InvalidSize(..))));
+    }
+
+    #[cfg(feature = "templates")]
+    #[tokio::test]
+    async fn renders_runtime_template() {
+        let screen = || Screen::new(-36.85, 174.76, "Pacific/Auckland", &[]).unwrap();
+        let template = concat!(env!("CARGO_MANIFEST_DIR"), "/templates/home.svg");
+
+        // The example template is the built-in layout
+        let built_in = screen().render_placeholder().await.unwrap();
+        let runtime = screen().with_template(template).render_placeholder().await.unwrap();
+        assert!(built_in == runtime);
+
+        let missing = screen().with_template("missing.svg").render_placeholder().await;
+        assert!(matches!(missing, Err(Error::Template(_))));
+
+        let dir = std::env::temp_dir().join(format!("epd-home-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("broken.svg"), "<svg><text>{{ time }}</svg>").unwrap();
+        let broken = screen().with_template(dir.join("broken.svg")).render_placeholder().await;
+        assert!(matches!(broken, Err(Error::InvalidSvg(_))));
+    }
+
+    #[cfg(feature = "templates")]
+    #[tokio::test]
+    async fn rejects_bad_date_formats_in_runtime_templates() {
+        let dir = std::env::temp_dir().join(format!("epd-home-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("bad-date.svg"), r#"<svg><text>{{ time|formatdate("%Q") }}</text></svg>"#).unwrap();
+
+        let screen = Screen::new(-36.85, 174.76, "Pacific/Auckland", &[]).unwrap();
+        let rendered = screen.with_template(dir.join("bad-date.svg")).render_placeholder().await;
+        assert!(matches!(rendered, Err(Error::Template(_))));
+    }
+
+    struct UnusedArrivals;
+
+    #[async_trait]
+    impl ArrivalsSource for UnusedArrivals {
+        async fn stop_arrivals(&self, _stop_code: &str) -> Result<Vec<RouteArrivals>> {
+            panic!("no widget shows arrivals")
+        }
+    }
+
+    #[tokio::test]
+    async fn renders_widget_layout() {
+        use crate::layout::{Placement, Widget};
+
+        let layout = Layout::new(2, 2)
+            .with_widget(Placement::new(Widget::Clock, 0, 0))
+            .with_widget(Placement::new(Widget::Weather, 1, 0))
+            .with_widget(Placement::new(Widget::Forecast, 0, 1).with_span(2, 1));
+        let img = Screen::new(-36.85, 174.76, "Pacific/Auckland", &["NX1"])
+            .unwrap()
+            .with_weather_source(Arc::new(FakeWeather))
+            .with_arrivals_source(Arc::new(UnusedArrivals))
+            .with_size(480, 800)
+            .unwrap()
+            .with_layout(layout)
+            .render()
+            .await
+            .unwrap();
+
+        // Laid out on the panel's own shape rather than scaled down from 800 x 480
+        assert_eq!((img.width(), img.height()), (480, 800));
+        assert!(img.crop(0, 400, 480, 400).pixels().any(|px| px == Ink::Black));
+
+        let off_grid = Layout::new(1, 1).with_widget(Placement::new(Widget::Clock, 1, 0));
+        let screen = Screen::new(-36.85, 174.76, "Pacific/Auckland", &[]).unwrap();
+        let result = screen.with_layout(off_grid).render_placeholder().await;
+        assert!(matches!(result, Err(Error::InvalidLayout(_))));
+    }
+
+    #[tokio::test]
+    async fn dithers_only_images_and_gradients() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="96" height="32">
+            <defs><linearGradient id="fade"><stop offset="0" stop-color="#000"/><stop offset="1" stop-color="#fff"/></linearGradient></defs>
+            <rect x="0" y="0" width="32" height="32" fill="#777"/>
+            <rect x="32" y="0" width="32" height="32" fill="url(#fade)"/>
+            <g id="dither-photo"><rect x="64" y="0" width="32" height="32" fill="#777"/></g>
+        </svg>"##;
+        let img = render_svg(svg.as_bytes().to_vec(), &STUCKI.into(), Colors::BlackWhite, None).await.unwrap();
+
+        let whites = |x: u32| img.crop(x, 0, 32, 32).pixels().filter(|px| *px == Ink::White).count();
+        // A flat grey is thresholded to black, the rest are dithered
+        assert_eq!(whites(0), 0);
+        assert!((300..700).contains(&whites(32)));
+        assert!((300..700).contains(&whites(64)));
+    }
+
+    #[tokio::test]
+    async fn renders_when_a_source_fails() {
+        let screen = Screen::new(-36.85, 174.76, "Pacific/Auckland", &["NX1"])
+            .unwrap()
+            .with_weather_source(Arc::new(FakeWeather))
+            .with_arrivals_source(Arc::new(FailingArrivals));
+
+        let model = screen.gather_home().await;
+        assert!(model.arrivals.is_none());
+        assert_eq!(model.weather.as_ref().unwrap().temp_now, "15");
+
+        // Only the arrivals are marked unavailable, and the weather is still drawn
+        let svg = String::from_utf8(screen.svg(model).unwrap()).unwrap();
+        assert_eq!(svg.matches(r#"href="icons/wifi-off.svg""#).count(), 1);
+        assert!(svg.contains("Departures unavailable"));
+        assert!(!svg.contains("Weather unavailable"));
+        assert!(svg.contains("15°"));
+
+        let img = screen.render().await.unwrap();
+        assert!(img.crop(0, 0, 300, 480).pixels().any(|px| px == Ink::Black));
+    }
+}
@@ -0,0 +1,71 @@
+//! Layouts loaded from MiniJinja templates at runtime, instead of the compiled-in `home.svg`.
+//!
+//! A template gets the same data as `home.svg`, serialized:
+//!
+//! - `accent`: CSS colour for accented elements
+//! - `time`: the current time, as an RFC 3339 string in the screen's timezone
+//! - `weather`: `weather_now` (an icon name), `temp_now`, `as_of`, and `forecast`, a list of
+//!   `time`, `weather` and `temp`, where `temp` is missing for sunrise and sunset
+//! - `arrivals`: `as_of`, and `arrivals`, a list of `route`, `headsign` and `arrival_times`, each
+//!   with a `kind` of `now`, `minutes` or `time` and, for the last two, a `value`
+//! - `calendar`: for screens with calendars, `days`, today's then tomorrow's, each with a
+//!   `label`, its `date`, and `events`, a list of `summary` and `time`, missing for all-day events
+//! - `entities`: for screens with Home Assistant entities, `entities`, a list of `entity_id`,
+//!   `name`, `state` and `unit`, in the configured order. Pick one out with e.g.
+//!   `entities.entities|selectattr("entity_id", "eq", "sensor.lounge_temperature")|first`
+//!
+//! `weather`, `arrivals`, `calendar` and `entities` are missing if their source failed, and
+//! `as_of` is set when showing cached data. On top of MiniJinja's own filters, there's
+//! `formatdate` taking a `strftime` format, `titlecase`, and `icon`, which turns an icon name into
+//! an `href` for an `<image>`. Output is escaped for XML.
+//!
+//! Templates are read each time they're rendered, so changes show up on the next refresh.
+
+use std::{fmt::Write, path::Path};
+
+use chrono::DateTime;
+use minijinja::{path_loader, AutoEscape, Environment};
+use serde::Serialize;
+
+use crate::screen::{Error, Icon, Result};
+
+/// Renders the template at `path` with `model`. Other templates in the same directory can be
+/// included or extended.
+pub(crate) fn render(path: &Path, model: &impl Serialize) -> Result<String> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::MissingData(format!("template {}", path.display())))?;
+
+    let mut env = Environment::new();
+    env.set_loader(path_loader(path.parent().unwrap_or(Path::new("."))));
+    env.set_auto_escape_callback(|_| AutoEscape::Html);
+    env.add_filter("formatdate", formatdate);
+    env.add_filter("titlecase", |input: &str| titlecase::titlecase(input));
+    env.add_filter("icon", icon);
+
+    Ok(env.get_template(name)?.render(model)?)
+}
+
+fn formatdate(date: &str, format: &str) -> Result<String, minijinja::Error> {
+    let date = DateTime::parse_from_rfc3339(date).map_err(|err| {
+        minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, "not a date").with_source(err)
+    })?;
+    // A bad format only fails while it's written out, and `to_string` would panic
+    let mut formatted = String::new();
+    write!(formatted, "{}", date.format(format)).map_err(|_| {
+        minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, format!("invalid date format {format:?}"))
+    })?;
+    Ok(formatted)
+}
+
+/// Severe weather icons are drawn in the accent colour.
+fn icon(name: &str) -> Result<String, minijinja::Error> {
+    let icon: Icon = serde_json::from_value(name.into()).map_err(|err| {
+        minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, "unknown icon").with_source(err)
+    })?;
+    match icon.accent() {
+        true => Ok(format!("icons/accent/{}.svg", icon)),
+        false => Ok(format!("icons/{}.svg", icon)),
+    }
+}
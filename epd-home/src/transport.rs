@@ -1,6 +1,66 @@
 use askama::Template;
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
 use serde::Deserialize;
 
+use crate::screen::Result;
+
+/// Upcoming arrivals for one route at a stop, independent of where they came from.
+#[derive(Debug, Clone)]
+pub struct RouteArrivals {
+    pub route: String,
+    pub headsign: String,
+    /// Soonest first.
+    pub arrival_times: Vec<DateTime<Utc>>,
+}
+
+/// Something that can look up upcoming arrivals by stop code.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait ArrivalsSource: Send + Sync {
+    /// Returns an empty list if the stop is unknown.
+    async fn stop_arrivals(&self, stop_code: &str) -> Result<Vec<RouteArrivals>>;
+
+    /// Tells this source's stops apart from other sources' in a shared
+    /// [`SourceCache`](crate::cache::SourceCache). Sources of the same type reading different
+    /// feeds should override this.
+    fn cache_key(&self) -> String {
+        std::any::type_name::<Self>().to_string()
+    }
+}
+
+/// The default arrivals source, backed by next-at-api (Auckland Transport).
+#[derive(Default)]
+pub struct NextAt;
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl ArrivalsSource for NextAt {
+    async fn stop_arrivals(&self, stop_code: &str) -> Result<Vec<RouteArrivals>> {
+        let arrivals = get_stop_arrivals(stop_code)
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|arr| RouteArrivals {
+                route: arr.route_trip.route_short_name,
+                headsign: arr.route_trip.stop_headsign,
+                arrival_times: arr
+                    .arrivals
+                    .iter()
+                    .filter_map(|arr_time| {
+                        let time = arr_time
+                            .updated_arrival_timestamp
+                            .unwrap_or(arr_time.arrival_timestamp);
+                        Utc.timestamp_millis_opt(time).single()
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Ok(arrivals)
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Stop {
     pub id: String,
@@ -1,6 +1,9 @@
+use async_trait::async_trait;
 use reqwest::Url;
 use serde::Deserialize;
 
+use crate::screen::Result;
+
 // https://open-meteo.com/en/docs
 const WEATHER_URL: &str = "https://api.open-meteo.com/v1/forecast?current=temperature_2m,is_day,weather_code,wind_gusts_10m&hourly=temperature_2m,weather_code,is_day,wind_gusts_10m&daily=sunrise,sunset&forecast_days=2&timezone=UTC";
 
@@ -35,7 +38,7 @@ pub struct MeteoDailyWeather {
     pub sunset: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Weather {
     pub current: WeatherForecast,
     pub forecast: Vec<WeatherForecast>,
@@ -43,7 +46,7 @@ pub struct Weather {
     pub sunsets: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WeatherForecast {
     pub time: String,
     pub weather_code: u32,
@@ -52,6 +55,32 @@ pub struct WeatherForecast {
     pub wind_gusts: f64,
 }
 
+/// Something that can provide current conditions and an hourly forecast for a location.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait WeatherSource: Send + Sync {
+    /// Times are expected in UTC, formatted as `%Y-%m-%dT%H:%M`.
+    async fn fetch_weather(&self, latitude: f64, longitude: f64) -> Result<Weather>;
+
+    /// Tells this source's forecasts apart from other sources' in a shared
+    /// [`SourceCache`](crate::cache::SourceCache).
+    fn cache_key(&self) -> String {
+        std::any::type_name::<Self>().to_string()
+    }
+}
+
+/// The default weather source, backed by [Open-Meteo](https://open-meteo.com).
+#[derive(Default)]
+pub struct OpenMeteo;
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl WeatherSource for OpenMeteo {
+    async fn fetch_weather(&self, latitude: f64, longitude: f64) -> Result<Weather> {
+        Ok(fetch_weather(latitude, longitude).await?)
+    }
+}
+
 pub async fn fetch_weather(latitude: f64, longitude: f64) -> Result<Weather, reqwest::Error> {
     let mut url = Url::parse(WEATHER_URL).unwrap();
     url.query_pairs_mut()
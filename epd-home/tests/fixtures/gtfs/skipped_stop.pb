@@ -0,0 +1,7 @@
+
+
+2.0.
+1)
+
+T120240415
+x"S0"STN-1(
\ No newline at end of file
@@ -0,0 +1,15 @@
+
+
+2.0(
+1#
+
+T120240415"STN-1
+2
+
+T220240415#
+3
+
+T320240415"S2
+4
+
+T420240415 
\ No newline at end of file